@@ -0,0 +1,184 @@
+mod profile;
+
+use near_amm_client::{Action, AmmClient, FungibleTokenReceiverMessage, DEFAULT_GAS, U128};
+use near_primitives::account::AccessKey;
+use near_primitives::transaction::{
+    AddKeyAction, CreateAccountAction, DeleteAccountAction, DeployContractAction, TransferAction,
+};
+use near_primitives::types::Balance;
+use serde_json::json;
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+use profile::{parse_near_amount, Profile};
+
+const DEFAULT_PROFILE: &str = "script/profile.toml";
+
+const USAGE: &str = "usage: amm-cli [--profile <path>] <command>
+
+commands:
+  deploy                          deploy the AMM contract to the AMM account
+  init                            initialise the AMM with the LP as its owner and both tokens of the profile
+  add-liquidity <token> <amount>  seed the pool with liquidity of a token from the LP account
+  swap <token> <amount> [<min>]   swap a token from the user account for the other token,
+                                  refunded unless it gets at least min of the other token
+  quote <token> <amount>          get the amount out of swapping a token
+  metadata                        get the tokens and their ratios
+  reset                           delete and recreate the AMM account, then deploy the AMM again,
+                                  BEWARE this removes all tokens and data held by the AMM
+
+tokens are given as a or b for the tokens of the profile, or as their account ids, and amounts in their smallest unit
+the profile defaults to script/profile.toml";
+
+fn parse_amount(amount: &str) -> Result<Balance, String> {
+    amount.parse().map_err(|_| format!("invalid amount {}", amount))
+}
+
+// the AMM refunds what it cannot use instead of failing the transfer, e.g. a swap that would give too little,
+// so a transfer only did what was asked when the whole amount got used
+fn ensure_used(used: Balance, amount: Balance) -> Result<(), String> {
+    if used < amount {
+        return Err(format!(
+            "only {} of {} was used and the rest refunded, see the logs of the transaction for why",
+            used, amount
+        ));
+    }
+
+    Ok(())
+}
+
+fn deploy(profile: &Profile, client: &AmmClient) -> Result<(), String> {
+    let code = fs::read(&profile.amm_contract_location)
+        .map_err(|error| format!("cannot read {}: {}", profile.amm_contract_location.display(), error))?;
+    let signer = profile.signer(&profile.amm_acc_addr)?;
+    client
+        .send(&signer, &profile.amm_acc_addr, vec![Action::DeployContract(DeployContractAction { code })])
+        .map_err(|error| error.to_string())?;
+
+    println!("AMM deployed to {}", profile.amm_acc_addr);
+    Ok(())
+}
+
+fn reset(profile: &Profile, client: &AmmClient) -> Result<(), String> {
+    let signer = profile.signer(&profile.amm_acc_addr)?;
+    let beneficiary_id = profile.master_acc_addr.as_str().parse().map_err(|_| "invalid master account")?;
+    client
+        .send(&signer, &profile.amm_acc_addr, vec![Action::DeleteAccount(DeleteAccountAction { beneficiary_id })])
+        .map_err(|error| error.to_string())?;
+
+    // recreated with the same key, so its key file stays valid
+    let master = profile.signer(&profile.master_acc_addr)?;
+    let actions = vec![
+        Action::CreateAccount(CreateAccountAction {}),
+        Action::Transfer(TransferAction { deposit: parse_near_amount(&profile.amm_acc_init_near)? }),
+        Action::AddKey(AddKeyAction { public_key: signer.public_key.clone(), access_key: AccessKey::full_access() }),
+    ];
+    client.send(&master, &profile.amm_acc_addr, actions).map_err(|error| error.to_string())?;
+
+    for token_id in [&profile.token_a_acc_addr, &profile.token_b_acc_addr] {
+        client
+            .call(
+                &profile.signer(token_id)?,
+                token_id,
+                "storage_deposit",
+                &json!({ "account_id": profile.amm_acc_addr }),
+                DEFAULT_GAS,
+                parse_near_amount(&profile.ft_register_deposit)?,
+            )
+            .map_err(|error| error.to_string())?;
+    }
+
+    deploy(profile, client)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (profile_path, args) = match args {
+        [flag, path, args @ ..] if flag == "--profile" => (PathBuf::from(path), args),
+        _ => (PathBuf::from(DEFAULT_PROFILE), args),
+    };
+    let profile = Profile::load(&profile_path)?;
+    let client = AmmClient::new(&profile.rpc_url, profile.amm_acc_addr.clone()).map_err(|error| error.to_string())?;
+
+    match args {
+        [command] if command == "deploy" => deploy(&profile, &client),
+        [command] if command == "init" => {
+            let args = json!({
+                "owner_id": profile.lp_acc_addr,
+                "token_a_id": profile.token_a_acc_addr,
+                "token_b_id": profile.token_b_acc_addr,
+            });
+            client
+                .call_contract(&profile.signer(&profile.lp_acc_addr)?, "new", &args, 0)
+                .map_err(|error| error.to_string())?;
+
+            println!("AMM initialised, its metadata is loaded once both tokens called back");
+            Ok(())
+        }
+        [command, token, amount] if command == "add-liquidity" => {
+            let signer = profile.signer(&profile.lp_acc_addr)?;
+            let message = FungibleTokenReceiverMessage::LPDeposit;
+            let amount = parse_amount(amount)?;
+            let used = client
+                .ft_transfer_call(&signer, &profile.token(token)?, amount, &message)
+                .map_err(|error| error.to_string())?;
+            ensure_used(used, amount)?;
+
+            println!("Liquidity added: {}", used);
+            Ok(())
+        }
+        [command, token, amount, min_amount_out @ ..] if command == "swap" && min_amount_out.len() <= 1 => {
+            let signer = profile.signer(&profile.user_acc_addr)?;
+            let message = FungibleTokenReceiverMessage::Swap {
+                receiver_id: None,
+                receiver_msg: None,
+                referral_id: None,
+                min_amount_out: min_amount_out.first().map(|min| parse_amount(min).map(U128)).transpose()?,
+            };
+            let amount = parse_amount(amount)?;
+            let used = client
+                .ft_transfer_call(&signer, &profile.token(token)?, amount, &message)
+                .map_err(|error| error.to_string())?;
+            ensure_used(used, amount)?;
+
+            println!("Swapped: {}", used);
+            Ok(())
+        }
+        [command, token, amount] if command == "quote" => {
+            let amount_out = client
+                .get_amount_out(&profile.token(token)?, parse_amount(amount)?)
+                .map_err(|error| error.to_string())?;
+
+            println!("{}", amount_out);
+            Ok(())
+        }
+        [command] if command == "metadata" => {
+            let metadata = client.get_metadata().map_err(|error| error.to_string())?;
+
+            println!("{}", serde_json::to_string_pretty(&metadata).map_err(|error| error.to_string())?);
+            Ok(())
+        }
+        [command] if command == "reset" => reset(&profile, &client),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_used() {
+        assert_eq!(ensure_used(100, 100), Ok(()));
+        // a refunded swap still ends in a successful transfer that used nothing
+        assert!(ensure_used(0, 100).is_err());
+        assert!(ensure_used(90, 100).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use near_amm_client::{AccountId, InMemorySigner};
+use near_crypto::{PublicKey, SecretKey};
+use near_primitives::types::Balance;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// yoctoNEAR per NEAR
+const NEAR_DECIMAL: usize = 24;
+
+// settings of a network and its accounts, the same as script/var.conf
+#[derive(Deserialize, Debug)]
+pub struct Profile {
+    pub rpc_url: String,
+    // key file of the master account, e.g. the validator key of a local sandbox node
+    pub master_key_path: PathBuf,
+    // key files of the other accounts named <account id>.json, as stored by near-cli
+    pub credentials_dir: PathBuf,
+    pub master_acc_addr: AccountId,
+    // in NEAR, e.g. "0.00125"
+    pub ft_register_deposit: String,
+    pub token_a_acc_addr: AccountId,
+    pub token_b_acc_addr: AccountId,
+    pub lp_acc_addr: AccountId,
+    pub user_acc_addr: AccountId,
+    pub amm_acc_addr: AccountId,
+    pub amm_acc_init_near: String,
+    pub amm_contract_location: PathBuf,
+}
+
+// key file as written by near-cli, which names the secret key private_key, or by nearcore
+#[derive(Deserialize)]
+struct KeyFile {
+    account_id: String,
+    public_key: PublicKey,
+    #[serde(alias = "private_key")]
+    secret_key: SecretKey,
+}
+
+impl Profile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let profile = fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        toml::from_str(&profile).map_err(|error| format!("invalid profile {}: {}", path.display(), error))
+    }
+
+    // signer of an account from its key file
+    pub fn signer(&self, account_id: &AccountId) -> Result<InMemorySigner, String> {
+        let path = if account_id == &self.master_acc_addr {
+            self.master_key_path.clone()
+        } else {
+            self.credentials_dir.join(format!("{}.json", account_id))
+        };
+        let key_file = fs::read_to_string(&path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        let key_file: KeyFile =
+            serde_json::from_str(&key_file).map_err(|error| format!("invalid key file {}: {}", path.display(), error))?;
+        if key_file.account_id != account_id.as_str() || key_file.secret_key.public_key() != key_file.public_key {
+            return Err(format!("key file {} is not a key of {}", path.display(), account_id));
+        }
+
+        Ok(InMemorySigner::from_secret_key(
+            key_file.account_id.parse().map_err(|_| format!("invalid account id {}", account_id))?,
+            key_file.secret_key,
+        ))
+    }
+
+    // token given as a or b for the tokens of the profile, or as its account id
+    pub fn token(&self, token: &str) -> Result<AccountId, String> {
+        match token {
+            "a" => Ok(self.token_a_acc_addr.clone()),
+            "b" => Ok(self.token_b_acc_addr.clone()),
+            _ => token.parse().map_err(|_| format!("invalid token {}", token)),
+        }
+    }
+}
+
+// amount of NEAR in yoctoNEAR, e.g. "0.00125" for 1250000000000000000000
+pub fn parse_near_amount(amount: &str) -> Result<Balance, String> {
+    let invalid = || format!("invalid amount of NEAR {}", amount);
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() || fraction.len() > NEAR_DECIMAL || !fraction.chars().all(|digit| digit.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    format!("{}{:0<width$}", whole, fraction, width = NEAR_DECIMAL)
+        .parse()
+        .map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_near_amount() {
+        assert_eq!(parse_near_amount("0.00125"), Ok(1_250_000_000_000_000_000_000));
+        assert_eq!(parse_near_amount("100"), Ok(100_000_000_000_000_000_000_000_000));
+        assert!(parse_near_amount(".5").is_err());
+        assert!(parse_near_amount("1.-5").is_err());
+        assert!(parse_near_amount("0.0000000000000000000000001").is_err());
+    }
+
+    #[test]
+    fn test_profile_of_the_scripts() {
+        let profile: Profile = toml::from_str(include_str!("../../script/profile.toml")).unwrap();
+        assert_eq!(profile.token("a").unwrap().as_str(), "eth.test.near");
+        assert_eq!(profile.token("sol.test.near").unwrap(), profile.token_b_acc_addr);
+        assert!(parse_near_amount(&profile.ft_register_deposit).is_ok());
+    }
+}
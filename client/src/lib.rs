@@ -0,0 +1,306 @@
+mod rpc;
+
+use near_crypto::Signer;
+use near_primitives::{
+    borsh::BorshSerialize,
+    hash::CryptoHash,
+    serialize::{from_base64, to_base64},
+    transaction::{FunctionCallAction, SignedTransaction, Transaction},
+    types::{Balance, Gas, Nonce},
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::fmt;
+
+pub use near_automated_market_maker::{ContractMetadata, FungibleTokenReceiverMessage, TokenMetadata};
+pub use near_crypto::InMemorySigner;
+pub use near_primitives::transaction::Action;
+pub use near_sdk::{json_types::U128, AccountId};
+pub use rpc::RpcClient;
+
+// enough for ft_transfer_call to run the AMM's ft_on_transfer, including its swap and the transfer out
+pub const GAS_FOR_FT_TRANSFER_CALL: Gas = 100_000_000_000_000;
+pub const DEFAULT_GAS: Gas = 30_000_000_000_000;
+pub const FT_TRANSFER_DEPOSIT_YOCTO_NEAR: Balance = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidUrl(String),
+    Io(std::io::Error),
+    Http(String),
+    Json(serde_json::Error),
+    // error answered by the node, e.g. an unknown account or an invalid transaction
+    Rpc(Value),
+    // transaction that was included but failed, with its final execution outcome
+    Execution(Value),
+    InvalidResponse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "RPC URL must be of the form http[s]://host[:port][/path], got {}", url),
+            Error::Io(error) => write!(f, "RPC connection failed: {}", error),
+            Error::Http(status) => write!(f, "RPC request failed: {}", status),
+            Error::Json(error) => write!(f, "RPC response is not valid JSON: {}", error),
+            Error::Rpc(error) => write!(f, "RPC node answered with an error: {}", error),
+            Error::Execution(outcome) => write!(f, "transaction failed: {}", outcome["status"]),
+            Error::InvalidResponse(message) => write!(f, "unexpected RPC response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+fn to_primitives_account_id(account_id: &AccountId) -> near_primitives::types::AccountId {
+    account_id.as_str().parse().expect("account ids of near-sdk and near-primitives share their rules")
+}
+
+pub fn function_call(method_name: &str, args: &Value, gas: Gas, deposit: Balance) -> FunctionCallAction {
+    FunctionCallAction {
+        method_name: method_name.to_string(),
+        args: args.to_string().into_bytes(),
+        gas,
+        deposit,
+    }
+}
+
+// transaction signed by the access key of the signer
+pub fn build_transaction(
+    signer: &InMemorySigner,
+    nonce: Nonce,
+    block_hash: CryptoHash,
+    receiver_id: &AccountId,
+    actions: Vec<Action>,
+) -> SignedTransaction {
+    let transaction = Transaction {
+        signer_id: signer.account_id.clone(),
+        public_key: signer.public_key(),
+        nonce,
+        receiver_id: to_primitives_account_id(receiver_id),
+        block_hash,
+        actions,
+    };
+    let signature = signer.sign(transaction.get_hash_and_size().0.as_ref());
+
+    SignedTransaction::new(signature, transaction)
+}
+
+// client of one AMM contract, building the same requests as script/*.sh from the types the contract uses
+pub struct AmmClient {
+    rpc: RpcClient,
+    contract_id: AccountId,
+}
+
+impl AmmClient {
+    pub fn new(rpc_url: &str, contract_id: AccountId) -> Result<Self, Error> {
+        Ok(Self {
+            rpc: RpcClient::new(rpc_url)?,
+            contract_id,
+        })
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub fn contract_id(&self) -> &AccountId {
+        &self.contract_id
+    }
+
+    // call a view method of any contract as of the final block
+    pub fn view<T: DeserializeOwned>(&self, contract_id: &AccountId, method_name: &str, args: &Value) -> Result<T, Error> {
+        let result = self.rpc.call(
+            "query",
+            json!({
+                "request_type": "call_function",
+                "finality": "final",
+                "account_id": contract_id,
+                "method_name": method_name,
+                "args_base64": to_base64(args.to_string()),
+            }),
+        )?;
+        let bytes: Vec<u8> = serde_json::from_value(result["result"].clone())?;
+
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn get_metadata(&self) -> Result<ContractMetadata, Error> {
+        self.view(&self.contract_id, "get_metadata", &json!({}))
+    }
+
+    pub fn get_amount_out(&self, token_in: &AccountId, amount_in: Balance) -> Result<Balance, Error> {
+        let amount_out: U128 = self.view(
+            &self.contract_id,
+            "get_amount_out",
+            &json!({ "token_in": token_in, "amount_in": U128(amount_in) }),
+        )?;
+
+        Ok(amount_out.0)
+    }
+
+    // sign the actions with the next nonce of the signer's access key and wait until they are final,
+    // returning the value the last action returned
+    pub fn send(&self, signer: &InMemorySigner, receiver_id: &AccountId, actions: Vec<Action>) -> Result<Value, Error> {
+        let access_key = self.rpc.call(
+            "query",
+            json!({
+                "request_type": "view_access_key",
+                "finality": "final",
+                "account_id": signer.account_id,
+                "public_key": signer.public_key(),
+            }),
+        )?;
+        let nonce = access_key["nonce"]
+            .as_u64()
+            .ok_or_else(|| Error::InvalidResponse("access key without nonce".to_string()))?;
+        let block_hash = access_key["block_hash"]
+            .as_str()
+            .and_then(|block_hash| block_hash.parse().ok())
+            .ok_or_else(|| Error::InvalidResponse("access key without block hash".to_string()))?;
+
+        let transaction = build_transaction(signer, nonce + 1, block_hash, receiver_id, actions);
+        let bytes = transaction
+            .try_to_vec()
+            .map_err(|error| Error::InvalidResponse(error.to_string()))?;
+        let outcome = self.rpc.call("broadcast_tx_commit", json!([to_base64(bytes)]))?;
+
+        get_success_value(outcome)
+    }
+
+    pub fn call(
+        &self,
+        signer: &InMemorySigner,
+        receiver_id: &AccountId,
+        method_name: &str,
+        args: &Value,
+        gas: Gas,
+        deposit: Balance,
+    ) -> Result<Value, Error> {
+        let call = function_call(method_name, args, gas, deposit);
+        self.send(signer, receiver_id, vec![Action::FunctionCall(call)])
+    }
+
+    // call a method of the AMM itself
+    pub fn call_contract(
+        &self,
+        signer: &InMemorySigner,
+        method_name: &str,
+        args: &Value,
+        deposit: Balance,
+    ) -> Result<Value, Error> {
+        self.call(signer, &self.contract_id, method_name, args, DEFAULT_GAS, deposit)
+    }
+
+    // transfer tokens to the AMM with a message for its ft_on_transfer, e.g. to provide liquidity or swap,
+    // returning the amount the AMM used, as whatever it refunded was given back by the token contract
+    pub fn ft_transfer_call(
+        &self,
+        signer: &InMemorySigner,
+        token_id: &AccountId,
+        amount: Balance,
+        message: &FungibleTokenReceiverMessage,
+    ) -> Result<Balance, Error> {
+        let args = json!({
+            "receiver_id": self.contract_id,
+            "amount": U128(amount),
+            "msg": serde_json::to_string(message)?,
+        });
+
+        let used = self.call(
+            signer,
+            token_id,
+            "ft_transfer_call",
+            &args,
+            GAS_FOR_FT_TRANSFER_CALL,
+            FT_TRANSFER_DEPOSIT_YOCTO_NEAR,
+        )?;
+        let used: U128 = serde_json::from_value(used)?;
+
+        Ok(used.0)
+    }
+}
+
+// value returned by a final execution outcome, which is JSON for the methods of the AMM and of token contracts
+fn get_success_value(outcome: Value) -> Result<Value, Error> {
+    match outcome["status"].get("SuccessValue").and_then(Value::as_str) {
+        Some("") => Ok(Value::Null),
+        Some(value) => {
+            let bytes = from_base64(value).map_err(|error| Error::InvalidResponse(error.to_string()))?;
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+        None => Err(Error::Execution(outcome)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::KeyType;
+    use near_primitives::borsh::BorshDeserialize;
+
+    use super::*;
+
+    fn signer() -> InMemorySigner {
+        InMemorySigner::from_seed("lp.test.near".parse().unwrap(), KeyType::ED25519, "lp.test.near")
+    }
+
+    #[test]
+    fn test_build_transaction() {
+        let args = json!({ "receiver_id": "amm.test.near", "amount": "100", "msg": "\"lp_deposit\"" });
+        let call = function_call("ft_transfer_call", &args, GAS_FOR_FT_TRANSFER_CALL, FT_TRANSFER_DEPOSIT_YOCTO_NEAR);
+        let transaction = build_transaction(
+            &signer(),
+            7,
+            CryptoHash::default(),
+            &"eth.test.near".parse().unwrap(),
+            vec![Action::FunctionCall(call)],
+        );
+
+        let transaction = SignedTransaction::try_from_slice(&transaction.try_to_vec().unwrap()).unwrap();
+        assert_eq!(transaction.transaction.nonce, 7);
+        assert_eq!(transaction.transaction.receiver_id.as_str(), "eth.test.near");
+        assert!(transaction
+            .signature
+            .verify(transaction.get_hash().as_ref(), &signer().public_key()));
+        match &transaction.transaction.actions[0] {
+            Action::FunctionCall(call) => assert_eq!(serde_json::from_slice::<Value>(&call.args).unwrap(), args),
+            action => panic!("expected a function call, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_messages_are_understood_by_the_contract() {
+        let message = FungibleTokenReceiverMessage::Swap {
+            receiver_id: None,
+            receiver_msg: None,
+            referral_id: Some("referrer.test.near".parse().unwrap()),
+            min_amount_out: Some(U128(90)),
+        };
+        let msg = serde_json::to_string(&message).unwrap();
+        assert_eq!(msg.parse::<FungibleTokenReceiverMessage>().unwrap(), message);
+        assert_eq!(serde_json::to_string(&FungibleTokenReceiverMessage::LPDeposit).unwrap(), "\"lp_deposit\"");
+    }
+
+    #[test]
+    fn test_get_success_value() {
+        let outcome = json!({ "status": { "SuccessValue": to_base64("\"100\"") } });
+        assert_eq!(get_success_value(outcome).unwrap(), json!("100"));
+        assert_eq!(get_success_value(json!({ "status": { "SuccessValue": "" } })).unwrap(), Value::Null);
+        assert!(matches!(
+            get_success_value(json!({ "status": { "Failure": {} } })),
+            Err(Error::Execution(_))
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use serde_json::{json, Value};
+
+use crate::Error;
+
+// JSON-RPC client of a NEAR node, over HTTPS for remote nodes, e.g. https://rpc.testnet.near.org, or plain HTTP as
+// served by local sandbox nodes, e.g. http://127.0.0.1:3030
+#[derive(Clone, Debug)]
+pub struct RpcClient {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let agent = ureq::Agent::new();
+        let is_http = url.starts_with("http://") || url.starts_with("https://");
+        if !is_http || agent.post(url).request_url().is_err() {
+            return Err(Error::InvalidUrl(url.to_string()));
+        }
+
+        Ok(Self {
+            agent,
+            url: url.to_string(),
+        })
+    }
+
+    // send a request and return its result, or the error the node answered with
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": "near-amm-client",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let response = match self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(response) => response,
+            // nodes answer some errors with an error status, but still with the JSON-RPC error in the body
+            Err(ureq::Error::Status(status, response)) => {
+                let status = format!("{} {}", status, response.status_text());
+                return match serde_json::from_str::<Value>(&response.into_string()?) {
+                    Ok(mut response) if response.get("error").is_some() => Err(Error::Rpc(response["error"].take())),
+                    _ => Err(Error::Http(status)),
+                };
+            }
+            Err(ureq::Error::Transport(transport)) => return Err(Error::Http(transport.to_string())),
+        };
+
+        let mut response: Value = serde_json::from_str(&response.into_string()?)?;
+        match response.get_mut("error") {
+            Some(error) => Err(Error::Rpc(error.take())),
+            None => Ok(response["result"].take()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    // node that answers a single request with the given response, returning the request it got
+    fn serve_once(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            // the request is complete once its JSON body is closed
+            while !request.ends_with(b"}") {
+                let size = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..size]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(RpcClient::new("http://127.0.0.1:3030").is_ok());
+        assert!(RpcClient::new("https://rpc.testnet.near.org").is_ok());
+        assert!(RpcClient::new("ws://127.0.0.1:3030").is_err());
+        assert!(RpcClient::new("http://").is_err());
+    }
+
+    #[test]
+    fn test_call() {
+        let (url, node) = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 48\r\n\r\n{\"jsonrpc\":\"2.0\",\"id\":\"1\",\"result\":{\"height\":7}}",
+        );
+        let result = RpcClient::new(&url).unwrap().call("status", json!([])).unwrap();
+        assert_eq!(result["height"], 7);
+        assert!(node.join().unwrap().contains("\"method\":\"status\""));
+    }
+
+    #[test]
+    fn test_call_error() {
+        let (url, _) = serve_once(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1c\r\n{\"error\":{\"name\":\"HANDLER\"}}\r\n0\r\n\r\n",
+        );
+        match RpcClient::new(&url).unwrap().call("query", json!({})) {
+            Err(Error::Rpc(error)) => assert_eq!(error["name"], "HANDLER"),
+            result => panic!("expected an RPC error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_call_error_status() {
+        let (url, _) = serve_once(
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 45\r\n\r\n{\"error\":{\"name\":\"REQUEST_VALIDATION_ERROR\"}}",
+        );
+        match RpcClient::new(&url).unwrap().call("query", json!({})) {
+            Err(Error::Rpc(error)) => assert_eq!(error["name"], "REQUEST_VALIDATION_ERROR"),
+            result => panic!("expected an RPC error, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::UnorderedSet,
+    json_types::U64,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId,
+};
+
+use crate::{Contract, ContractExt, StorageKey};
+
+// who can trade: anyone, only the accounts on the allowlist, or anyone but the accounts on the denylist
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AccessMode {
+    Open,
+    Allowlist,
+    Denylist,
+}
+
+// both lists are kept whichever mode is on, so switching modes does not lose either of them
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccessList {
+    mode: AccessMode,
+    allowlist: UnorderedSet<AccountId>,
+    denylist: UnorderedSet<AccountId>,
+}
+
+impl AccessList {
+    pub fn new() -> Self {
+        Self {
+            mode: AccessMode::Open,
+            allowlist: UnorderedSet::new(StorageKey::Allowlist),
+            denylist: UnorderedSet::new(StorageKey::Denylist),
+        }
+    }
+
+    pub fn is_allowed(&self, account_id: &AccountId) -> bool {
+        match self.mode {
+            AccessMode::Open => true,
+            AccessMode::Allowlist => self.allowlist.contains(account_id),
+            AccessMode::Denylist => !self.denylist.contains(account_id),
+        }
+    }
+}
+
+fn paginate(accounts: &UnorderedSet<AccountId>, from_index: Option<U64>, limit: Option<U64>) -> Vec<AccountId> {
+    accounts
+        .iter()
+        .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+        .take(limit.map_or(usize::MAX, |limit| limit.0 as usize))
+        .collect()
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get who can trade
+    pub fn get_access_mode(&self) -> AccessMode {
+        self.access_list.mode
+    }
+
+    // public method to check whether an account can trade under the current mode
+    pub fn is_allowed_to_trade(&self, account_id: AccountId) -> bool {
+        self.access_list.is_allowed(&account_id)
+    }
+
+    pub fn get_allowlist(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<AccountId> {
+        paginate(&self.access_list.allowlist, from_index, limit)
+    }
+
+    pub fn get_denylist(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<AccountId> {
+        paginate(&self.access_list.denylist, from_index, limit)
+    }
+
+    // only contract's owner can set who can trade
+    pub fn set_access_mode(&mut self, mode: AccessMode) {
+        self.assert_owner();
+
+        log!("Access mode changed from {:?} to {:?}!", self.access_list.mode, mode);
+        self.access_list.mode = mode;
+    }
+
+    // only contract's owner can change the lists, which apply once their mode is on
+    pub fn add_to_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_owner();
+        log!("Adding {} accounts to the allowlist!", account_ids.len());
+        self.access_list.allowlist.extend(account_ids);
+    }
+
+    pub fn remove_from_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_owner();
+        log!("Removing {} accounts from the allowlist!", account_ids.len());
+        account_ids.iter().for_each(|account_id| {
+            self.access_list.allowlist.remove(account_id);
+        });
+    }
+
+    pub fn add_to_denylist(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_owner();
+        log!("Adding {} accounts to the denylist!", account_ids.len());
+        self.access_list.denylist.extend(account_ids);
+    }
+
+    pub fn remove_from_denylist(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_owner();
+        log!("Removing {} accounts from the denylist!", account_ids.len());
+        account_ids.iter().for_each(|account_id| {
+            self.access_list.denylist.remove(account_id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_access_modes() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut access_list = AccessList::new();
+        access_list.allowlist.insert(&accounts(0));
+        access_list.denylist.insert(&accounts(1));
+        assert!(access_list.is_allowed(&accounts(1)));
+
+        access_list.mode = AccessMode::Allowlist;
+        assert!(access_list.is_allowed(&accounts(0)));
+        assert!(!access_list.is_allowed(&accounts(2)));
+
+        access_list.mode = AccessMode::Denylist;
+        assert!(!access_list.is_allowed(&accounts(1)));
+        assert!(access_list.is_allowed(&accounts(2)));
+    }
+}
@@ -0,0 +1,77 @@
+use near_sdk::{
+    assert_one_yocto, env,
+    json_types::U128,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId,
+};
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::{Contract, ContractExt};
+
+// one swap of a batch, paid from the deposit of the caller
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapAction {
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    // the whole batch fails when a swap gives less than this
+    pub min_amount_out: U128,
+    // the caller when not given
+    pub receiver_id: Option<AccountId>,
+    // the output is sent with ft_transfer_call when given, so the receiver gets notified
+    pub receiver_msg: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapResult {
+    pub token_out: AccountId,
+    pub amount_out: U128,
+    pub receiver_id: AccountId,
+}
+
+#[near_bindgen]
+impl Contract {
+    // run the swaps in order, each against the price left by the previous one, paying them from the caller's deposit
+    // and sending every output to its receiver, if any swap fails none of them happen
+    // outputs that cannot be delivered are deposited back for the caller
+    #[payable]
+    pub fn batch_swap(&mut self, actions: Vec<SwapAction>) -> Vec<SwapResult> {
+        assert_one_yocto();
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        let account_id = env::predecessor_account_id();
+        ensure(self.access_list.is_allowed(&account_id), AmmError::SwapperNotAllowed);
+
+        actions
+            .into_iter()
+            .map(|action| {
+                ensure(action.amount_in.0 > 0, AmmError::InvalidAmountTransferred);
+                self.debit_deposit(&account_id, &action.token_in, action.amount_in.0);
+
+                let (token_out, amount_out) = self
+                    .swap(&account_id, &action.token_in, action.amount_in.0, None)
+                    .unwrap_or_else(|error| panic_with(error));
+                ensure(amount_out >= action.min_amount_out.0, AmmError::AmountOutBelowMinimum);
+                self.fill_crossed_orders(&token_out);
+
+                let receiver_id = action.receiver_id.unwrap_or_else(|| account_id.clone());
+                log!("Batch swap of {} sends {} of token {} to {}!", account_id, amount_out, token_out, receiver_id);
+                self.deliver_token_out(
+                    account_id.clone(),
+                    receiver_id.clone(),
+                    token_out.clone(),
+                    amount_out,
+                    action.receiver_msg,
+                );
+
+                SwapResult {
+                    token_out,
+                    amount_out: U128(amount_out),
+                    receiver_id,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,244 @@
+use near_amm_math::{div_round, Rounding, BASIS_POINTS_DENOMINATOR, U256};
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
+    json_types::U64,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    Balance, BlockHeight,
+};
+
+use crate::error::*;
+use crate::{Contract, ContractExt};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapLimitsMetadata {
+    max_output_bps: u16,
+    max_price_move_per_block_bps: u32,
+    breaker_threshold_bps: u32,
+    breaker_window_blocks: U64,
+    pub block_price_move_bps: U64,
+    pub window_price_move_bps: U64,
+    pub tripped: bool,
+}
+
+// limits on how much a swap can take out of the pool and how fast the price can move, with a circuit breaker
+// that pauses trading once the price moved too far within a window of blocks
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SwapLimits {
+    // largest output of a single swap, in basis points of the reserve of the token out
+    max_output_bps: u16,
+    // largest price movement summed over the swaps of a block, in basis points
+    max_price_move_per_block_bps: u32,
+    // the breaker trips once the price movement summed over the swaps of a window goes past this
+    breaker_threshold_bps: u32,
+    breaker_window_blocks: BlockHeight,
+    block_height: BlockHeight,
+    block_price_move_bps: u64,
+    window_start_block_height: BlockHeight,
+    window_price_move_bps: u64,
+    // trading stays paused until the owner resets the breaker
+    tripped: bool,
+}
+
+impl SwapLimits {
+    pub fn new(
+        max_output_bps: u16,
+        max_price_move_per_block_bps: u32,
+        breaker_threshold_bps: u32,
+        breaker_window_blocks: BlockHeight,
+    ) -> Self {
+        ensure(
+            max_output_bps > 0 && max_output_bps <= BASIS_POINTS_DENOMINATOR && breaker_window_blocks > 0,
+            AmmError::InvalidSwapLimits,
+        );
+
+        Self {
+            max_output_bps,
+            max_price_move_per_block_bps,
+            breaker_threshold_bps,
+            breaker_window_blocks,
+            block_height: env::block_height(),
+            block_price_move_bps: 0,
+            window_start_block_height: env::block_height(),
+            window_price_move_bps: 0,
+            tripped: false,
+        }
+    }
+
+    pub fn get_metadata(&self) -> SwapLimitsMetadata {
+        SwapLimitsMetadata {
+            max_output_bps: self.max_output_bps,
+            max_price_move_per_block_bps: self.max_price_move_per_block_bps,
+            breaker_threshold_bps: self.breaker_threshold_bps,
+            breaker_window_blocks: U64(self.breaker_window_blocks),
+            block_price_move_bps: U64(self.get_block_price_move()),
+            window_price_move_bps: U64(self.get_window_price_move()),
+            tripped: self.tripped,
+        }
+    }
+
+    // price movement so far in the current block
+    fn get_block_price_move(&self) -> u64 {
+        if env::block_height() == self.block_height {
+            self.block_price_move_bps
+        } else {
+            0
+        }
+    }
+
+    // price movement so far in the current window, which starts over once the window has passed
+    fn get_window_price_move(&self) -> u64 {
+        if env::block_height() < self.window_start_block_height.saturating_add(self.breaker_window_blocks) {
+            self.window_price_move_bps
+        } else {
+            0
+        }
+    }
+
+    // whether a swap giving amount_out and moving the price by price_move_bps would stay within the limits
+    pub fn check(&self, amount_out: Balance, balance_out: Balance, price_move_bps: u64) -> Result<(), AmmError> {
+        if self.tripped {
+            return Err(AmmError::TradingPaused);
+        }
+
+        let max_output = div_round(
+            U256::from(balance_out) * U256::from(self.max_output_bps),
+            U256::from(BASIS_POINTS_DENOMINATOR),
+            Rounding::Down,
+        );
+        if U256::from(amount_out) > max_output {
+            return Err(AmmError::SwapOutputTooLarge);
+        }
+
+        if self.get_block_price_move().saturating_add(price_move_bps) > u64::from(self.max_price_move_per_block_bps) {
+            return Err(AmmError::PriceMoveTooLarge);
+        }
+
+        Ok(())
+    }
+
+    // add the price movement of a swap to the block and the window, tripping the breaker when the window went too far
+    pub fn record_price_move(&mut self, price_move_bps: u64) {
+        let block_height = env::block_height();
+        self.block_price_move_bps = self.get_block_price_move().saturating_add(price_move_bps);
+        self.block_height = block_height;
+
+        let window_price_move_bps = self.get_window_price_move();
+        if window_price_move_bps == 0 {
+            self.window_start_block_height = block_height;
+        }
+        self.window_price_move_bps = window_price_move_bps.saturating_add(price_move_bps);
+
+        if self.window_price_move_bps > u64::from(self.breaker_threshold_bps) {
+            log!(
+                "Circuit breaker tripped as the price moved {} basis points since block {}, trading is paused!",
+                self.window_price_move_bps,
+                self.window_start_block_height
+            );
+            self.tripped = true;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.tripped = false;
+        self.window_start_block_height = env::block_height();
+        self.window_price_move_bps = 0;
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the swap limits and the state of the circuit breaker, none when swaps are not limited
+    pub fn get_swap_limits(&self) -> Option<SwapLimitsMetadata> {
+        self.swap_limits.as_ref().map(|swap_limits| swap_limits.get_metadata())
+    }
+
+    // limit the output of a single swap and the price movement per block, and pause trading once the price moved past
+    // the threshold within the window, only contract's owner can set the limits
+    pub fn set_swap_limits(
+        &mut self,
+        max_output_bps: u16,
+        max_price_move_per_block_bps: u32,
+        breaker_threshold_bps: u32,
+        breaker_window_blocks: U64,
+    ) {
+        self.assert_owner();
+
+        log!(
+            "Swaps limited to {} basis points of the reserve and {} basis points of price movement per block!",
+            max_output_bps,
+            max_price_move_per_block_bps
+        );
+        self.swap_limits = Some(SwapLimits::new(
+            max_output_bps,
+            max_price_move_per_block_bps,
+            breaker_threshold_bps,
+            breaker_window_blocks.0,
+        ));
+    }
+
+    // only contract's owner can remove the swap limits, which resumes trading if the breaker tripped
+    pub fn remove_swap_limits(&mut self) {
+        self.assert_owner();
+
+        log!("Swap limits removed!");
+        self.swap_limits = None;
+    }
+
+    // resume trading after the circuit breaker tripped, only contract's owner can reset it
+    pub fn reset_circuit_breaker(&mut self) {
+        self.assert_owner();
+        self.swap_limits
+            .as_mut()
+            .or_panic(AmmError::InvalidSwapLimits)
+            .reset();
+
+        log!("Circuit breaker reset, trading resumes!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn set_block_height(block_height: BlockHeight) {
+        testing_env!(VMContextBuilder::new().block_index(block_height).build());
+    }
+
+    #[test]
+    fn test_swap_limits() {
+        set_block_height(100);
+        let mut swap_limits = SwapLimits::new(1_000, 500, 800, 10);
+        assert_eq!(swap_limits.check(100, 1_000, 0), Ok(()));
+        assert_eq!(swap_limits.check(101, 1_000, 0), Err(AmmError::SwapOutputTooLarge));
+        assert_eq!(swap_limits.check(100, 1_000, 501), Err(AmmError::PriceMoveTooLarge));
+
+        swap_limits.record_price_move(400);
+        assert_eq!(swap_limits.check(100, 1_000, 101), Err(AmmError::PriceMoveTooLarge));
+
+        // the block limit starts over every block, but the window keeps counting
+        set_block_height(101);
+        assert_eq!(swap_limits.check(100, 1_000, 101), Ok(()));
+        swap_limits.record_price_move(450);
+        assert_eq!(swap_limits.check(0, 1_000, 0), Err(AmmError::TradingPaused));
+
+        swap_limits.reset();
+        assert_eq!(swap_limits.check(0, 1_000, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_window_starts_over() {
+        set_block_height(100);
+        let mut swap_limits = SwapLimits::new(1_000, 500, 800, 10);
+        swap_limits.record_price_move(450);
+
+        set_block_height(110);
+        swap_limits.record_price_move(450);
+        assert!(!swap_limits.get_metadata().tripped);
+    }
+}
@@ -0,0 +1,185 @@
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::U128,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance, Promise, PromiseResult,
+};
+
+use crate::error::*;
+use crate::util::DEPOSIT_RECORD_STORAGE_BYTES;
+use crate::{Contract, ContractExt, StorageKey};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenDeposit {
+    token_id: AccountId,
+    pub amount: U128,
+}
+
+// tokens deposited by accounts into the contract to be spent by later calls, e.g. batch_swap, or withdrawn
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Deposits {
+    // keyed by account and then token
+    balances: LookupMap<(AccountId, AccountId), Balance>,
+    // total deposited per token, which is held by the contract but is not liquidity
+    totals: LookupMap<AccountId, Balance>,
+}
+
+impl Deposits {
+    pub fn new() -> Self {
+        Self {
+            balances: LookupMap::new(StorageKey::Deposits),
+            totals: LookupMap::new(StorageKey::DepositTotals),
+        }
+    }
+
+    pub fn get(&self, account: &AccountId, token: &AccountId) -> Balance {
+        self.balances
+            .get(&(account.clone(), token.clone()))
+            .unwrap_or(0)
+    }
+
+    pub fn get_total(&self, token: &AccountId) -> Balance {
+        self.totals.get(token).unwrap_or(0)
+    }
+
+    // returns whether a new balance was stored
+    pub fn credit(&mut self, account: &AccountId, token: &AccountId, amount: Balance) -> bool {
+        let previous = self.get(account, token);
+        let balance = previous
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        let total = self
+            .get_total(token)
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        self.balances.insert(&(account.clone(), token.clone()), &balance);
+        self.totals.insert(token, &total);
+
+        previous == 0
+    }
+
+    // returns whether the balance was emptied and removed
+    pub fn debit(&mut self, account: &AccountId, token: &AccountId, amount: Balance) -> bool {
+        let balance = self
+            .get(account, token)
+            .checked_sub(amount)
+            .or_panic(AmmError::InsufficientDeposit);
+        let total = self
+            .get_total(token)
+            .checked_sub(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        // emptied balances are removed so they do not hold on to storage
+        if balance == 0 {
+            self.balances.remove(&(account.clone(), token.clone()));
+        } else {
+            self.balances.insert(&(account.clone(), token.clone()), &balance);
+        }
+        self.totals.insert(token, &total);
+
+        balance == 0
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the deposit of an account in every token of the pool
+    pub fn get_deposits(&self, account_id: AccountId) -> Vec<TokenDeposit> {
+        self.tokens
+            .iter()
+            .map(|token| TokenDeposit {
+                token_id: token.get_address().clone(),
+                amount: U128(self.deposits.get(&account_id, token.get_address())),
+            })
+            .collect()
+    }
+
+    // send deposited tokens back to the caller, the whole deposit of the token when no amount is given
+    #[payable]
+    pub fn withdraw(&mut self, token_id: AccountId, amount: Option<U128>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let amount = amount.map_or_else(|| self.deposits.get(&account_id, &token_id), |amount| amount.0);
+        ensure(amount > 0, AmmError::InvalidAmountTransferred);
+        self.debit_deposit(&account_id, &token_id, amount);
+
+        log!("Withdrawing {} of token {} to {}!", amount, token_id, account_id);
+        self.transfers_in_flight.add(&token_id, amount);
+        self.send_token_out(account_id.clone(), token_id.clone(), amount)
+            .then(Self::ext(env::current_account_id()).post_withdraw(account_id, token_id, U128(amount)))
+    }
+
+    // callback for sending withdrawn tokens, which are deposited back if they could not be sent
+    #[private]
+    pub fn post_withdraw(&mut self, account_id: AccountId, token_id: AccountId, amount: U128) {
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
+
+        self.transfers_in_flight.settle(&token_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {}
+            PromiseResult::Failed => {
+                log!("Withdrawing to {} failed, depositing {} of token {} back!", account_id, amount.0, token_id);
+                self.credit_owed_deposit(&account_id, &token_id, amount.0);
+            }
+        }
+    }
+
+    // deposit tokens the account chose to deposit, the storage of a new balance is charged to the account
+    pub(crate) fn credit_deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        if self.deposits.get(account_id, token_id) == 0 {
+            self.storage_accounts
+                .charge(account_id, DEPOSIT_RECORD_STORAGE_BYTES)
+                .unwrap_or_panic();
+        }
+        self.deposits.credit(account_id, token_id, amount);
+    }
+
+    // deposit tokens owed to the account, e.g. a payout that could not be delivered, which must not fail
+    // the storage of a new balance is charged to the account when it can pay for it, otherwise the contract pays
+    pub(crate) fn credit_owed_deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        if self.deposits.credit(account_id, token_id, amount) {
+            let _ = self.storage_accounts.charge(account_id, DEPOSIT_RECORD_STORAGE_BYTES);
+        }
+    }
+
+    pub(crate) fn debit_deposit(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        if self.deposits.debit(account_id, token_id, amount) {
+            self.storage_accounts.release(account_id, DEPOSIT_RECORD_STORAGE_BYTES);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_deposits() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut deposits = Deposits::new();
+        deposits.credit(&accounts(0), &accounts(2), 100);
+        deposits.credit(&accounts(1), &accounts(2), 50);
+        deposits.debit(&accounts(0), &accounts(2), 30);
+
+        assert_eq!(deposits.get(&accounts(0), &accounts(2)), 70);
+        assert_eq!(deposits.get(&accounts(0), &accounts(3)), 0);
+        assert_eq!(deposits.get_total(&accounts(2)), 120);
+    }
+
+    #[test]
+    #[should_panic(expected = "E700: ")]
+    fn test_illegal_debit() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut deposits = Deposits::new();
+        deposits.credit(&accounts(0), &accounts(2), 100);
+        deposits.debit(&accounts(0), &accounts(2), 101);
+    }
+}
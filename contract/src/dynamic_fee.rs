@@ -0,0 +1,149 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
+    json_types::U64,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    BlockHeight,
+};
+use std::cmp;
+
+use crate::error::*;
+use crate::util::{MAX_SWAP_FEE_BPS, PRICE_MOVE_BPS_PER_FEE_BPS};
+use crate::{Contract, ContractExt};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DynamicFeeMetadata {
+    floor_bps: u16,
+    ceiling_bps: u16,
+    decay_bps_per_block: u16,
+    pub volatility_bps: U64,
+    // fee the next swap gets charged
+    pub fee_bps: u16,
+}
+
+// swap fee that rises with recent price movement and falls back to its floor as the movement decays
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DynamicFee {
+    floor_bps: u16,
+    ceiling_bps: u16,
+    // how much of the accumulated price movement is forgotten every block
+    decay_bps_per_block: u16,
+    // accumulator of the price movement of every swap, in basis points of the price before the swap,
+    // as of last_block_height
+    volatility_bps: u64,
+    last_block_height: BlockHeight,
+}
+
+impl DynamicFee {
+    pub fn new(floor_bps: u16, ceiling_bps: u16, decay_bps_per_block: u16) -> Self {
+        ensure(ceiling_bps <= MAX_SWAP_FEE_BPS, AmmError::InvalidSwapFee);
+        ensure(floor_bps <= ceiling_bps, AmmError::InvalidDynamicFee);
+
+        Self {
+            floor_bps,
+            ceiling_bps,
+            decay_bps_per_block,
+            volatility_bps: 0,
+            last_block_height: env::block_height(),
+        }
+    }
+
+    pub fn get_metadata(&self) -> DynamicFeeMetadata {
+        DynamicFeeMetadata {
+            floor_bps: self.floor_bps,
+            ceiling_bps: self.ceiling_bps,
+            decay_bps_per_block: self.decay_bps_per_block,
+            volatility_bps: U64(self.get_volatility()),
+            fee_bps: self.get_fee(),
+        }
+    }
+
+    // accumulated price movement decayed linearly up to the current block
+    pub fn get_volatility(&self) -> u64 {
+        let blocks = env::block_height().saturating_sub(self.last_block_height);
+        self.volatility_bps
+            .saturating_sub(blocks.saturating_mul(u64::from(self.decay_bps_per_block)))
+    }
+
+    pub fn get_fee(&self) -> u16 {
+        let fee_bps = u64::from(self.floor_bps).saturating_add(self.get_volatility() / PRICE_MOVE_BPS_PER_FEE_BPS);
+        cmp::min(fee_bps, u64::from(self.ceiling_bps)) as u16
+    }
+
+    pub fn record_price_move(&mut self, price_move_bps: u64) {
+        self.volatility_bps = self.get_volatility().saturating_add(price_move_bps);
+        self.last_block_height = env::block_height();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the parameters and the current state of the dynamic fee, none when the fee is fixed
+    pub fn get_dynamic_fee(&self) -> Option<DynamicFeeMetadata> {
+        self.dynamic_fee.as_ref().map(|dynamic_fee| dynamic_fee.get_metadata())
+    }
+
+    // switch to the dynamic fee with the given parameters, which starts without any price movement,
+    // only contract's owner can set it
+    pub fn set_dynamic_fee(&mut self, floor_bps: u16, ceiling_bps: u16, decay_bps_per_block: u16) {
+        self.assert_owner();
+
+        log!(
+            "Dynamic swap fee set between {} and {} basis points, decaying {} basis points of price movement per block!",
+            floor_bps,
+            ceiling_bps,
+            decay_bps_per_block
+        );
+        self.dynamic_fee = Some(DynamicFee::new(floor_bps, ceiling_bps, decay_bps_per_block));
+    }
+
+    // switch back to the fixed fee set via set_swap_fee, only contract's owner can remove the dynamic fee
+    pub fn remove_dynamic_fee(&mut self) {
+        self.assert_owner();
+
+        log!("Dynamic swap fee removed, swaps get charged {} basis points!", self.swap_fee_bps);
+        self.dynamic_fee = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn set_block_height(block_height: BlockHeight) {
+        testing_env!(VMContextBuilder::new().block_index(block_height).build());
+    }
+
+    #[test]
+    fn test_dynamic_fee() {
+        set_block_height(100);
+        let mut dynamic_fee = DynamicFee::new(5, 100, 10);
+        assert_eq!(dynamic_fee.get_fee(), 5);
+
+        // a move of 3% adds 30 basis points to the fee
+        dynamic_fee.record_price_move(300);
+        assert_eq!(dynamic_fee.get_fee(), 35);
+
+        set_block_height(110);
+        assert_eq!(dynamic_fee.get_volatility(), 200);
+        assert_eq!(dynamic_fee.get_fee(), 25);
+
+        dynamic_fee.record_price_move(5_000);
+        assert_eq!(dynamic_fee.get_fee(), 100);
+
+        set_block_height(1_000);
+        assert_eq!(dynamic_fee.get_fee(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "E516: ")]
+    fn test_illegal_dynamic_fee() {
+        set_block_height(100);
+        DynamicFee::new(50, 40, 10);
+    }
+}
@@ -1,29 +1,333 @@
-pub const OWNER_CANNOT_BE_CONTRACT_ACCOUNT_ITSELF: &str =
-    "The contract owner who is the liquidity provider cannot be the contract account itself";
-pub const DUPLICATE_TOKENS: &str = "Token A and Token B cannot be the same.";
-pub const SWAPPER_CANNOT_BE_CONTRACT_ACCOUNT_ITSELF: &str =
-    "The person who wishes to swap the token cannot be the contract account itself";
-
-
-pub const PROMISE_TOO_MANY_RESULTS: &str =
-    "Cross contract call returned more than one promise result.";
-pub const PROMISE_WRONG_VALUE_RECEIVED: &str = "Cross contract call returned invalid value.";
-pub const PROMISE_CALL_FAILED: &str = "Cross contract call failed.";
-
-pub const INVALID_TOKEN_DECIMAL: &str = "Token decimal is either too small (<1) or too big (>24).";
-pub const INTERNAL_OVERFLOW_ERROR: &str =
-    "There is an internal error when calculating due to overflow.";
-pub const INTERNAL_INDEX_ERROR: &str =
-    "There is an internal error when calculating due to index out of range.";
-
-pub const AMM_NOT_FUNCTIONAL_YET: &str = "This AMM contract is not yet fully functional — most likely because liquidity has not been provided.";
-pub const TOKEN_METADATA_NOT_INITIALISED: &str = "Token metadata has not been initialised.";
-pub const INVALID_TOKEN_BALANCE: &str = "Token balance has reached 0.";
-pub const SLIPPAGE: &str = "Token out is 0 — slippage happened!";
-
-pub const INVALID_TOKEN_RECEIVER_MESSAGE: &str =
-    "Invalid fungible token token receiver message, should be either 'lp_deposit' or 'swap'.";
-pub const INVALID_LP_DEPOSIT_SENDER: &str = "lp_deposit sender is not the owner of this AMM.";
-pub const INVALID_TOKEN_TRANSFERRED: &str =
-    "Transferred token is not one of two tokens set on this AMM.";
-pub const INVALID_AMOUNT_TRANSFERRED: &str = "Transferred amount cannot be zero.";
+use near_amm_math::MathError;
+use near_sdk::serde::{Deserialize, Serialize};
+use std::fmt;
+
+// every failure of this AMM, whose numeric code is part of the public interface so it must never be reused or changed
+// codes are grouped by hundreds: ownership, cross-contract calls, state, internal, pool, token receiver, and deposits
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AmmError {
+    OwnerCannotBeContractAccountItself = 100,
+    DuplicateTokens = 101,
+    CallerNotOwner = 102,
+    CallerNotPendingOwner = 103,
+    NoPendingOwner = 104,
+    SwapperCannotBeContractAccountItself = 105,
+    CallerNotOrderOwner = 106,
+    SwapperNotAllowed = 107,
+
+    PromiseTooManyResults = 200,
+    PromiseWrongValueReceived = 201,
+    PromiseCallFailed = 202,
+    PromiseUnexpectedResultCount = 203,
+
+    StateNotInitialised = 300,
+    InvalidStateVersion = 301,
+    UpgradeCodeMissing = 302,
+
+    InternalOverflowError = 400,
+    InternalIndexError = 401,
+
+    AmmNotFunctionalYet = 500,
+    TokenMetadataNotInitialised = 501,
+    TokenMetadataNotLoaded = 502,
+    TokenMetadataAlreadyLoaded = 503,
+    InvalidTokenBalance = 504,
+    InsufficientLiquidity = 505,
+    Slippage = 506,
+    PoolLocked = 507,
+    InvalidFeeBasisPoints = 508,
+    NoFlashLoanOutstanding = 509,
+    LimitOrderNotFound = 510,
+    InvalidLimitOrder = 511,
+    TooManyLimitOrders = 512,
+    AmountOutBelowMinimum = 513,
+    InvalidSwapFee = 514,
+    ReferralNotWhitelisted = 515,
+    InvalidDynamicFee = 516,
+    SwapOutputTooLarge = 517,
+    PriceMoveTooLarge = 518,
+    TradingPaused = 519,
+    InvalidSwapLimits = 520,
+    WrapNearNotSupported = 521,
+    InvalidSwapHistorySize = 522,
+    InsufficientStorageDeposit = 523,
+    InvalidRewardSchedule = 524,
+    NoRewardsToClaim = 525,
+    SharesBelowMinimum = 526,
+    InsufficientShares = 527,
+    LastLpRemovalInOneToken = 528,
+
+    InvalidTokenReceiverMessage = 600,
+    InvalidLpDepositSender = 601,
+    InvalidTokenTransferred = 602,
+    InvalidAmountTransferred = 603,
+
+    InsufficientDeposit = 700,
+    AccountNotRegistered = 701,
+    InsufficientStorageBalance = 702,
+    AccountHasRecords = 703,
+}
+
+impl AmmError {
+    // every error in code order, which is what SDKs generate their bindings from
+    pub const ALL: [AmmError; 54] = [
+        AmmError::OwnerCannotBeContractAccountItself,
+        AmmError::DuplicateTokens,
+        AmmError::CallerNotOwner,
+        AmmError::CallerNotPendingOwner,
+        AmmError::NoPendingOwner,
+        AmmError::SwapperCannotBeContractAccountItself,
+        AmmError::CallerNotOrderOwner,
+        AmmError::SwapperNotAllowed,
+        AmmError::PromiseTooManyResults,
+        AmmError::PromiseWrongValueReceived,
+        AmmError::PromiseCallFailed,
+        AmmError::PromiseUnexpectedResultCount,
+        AmmError::StateNotInitialised,
+        AmmError::InvalidStateVersion,
+        AmmError::UpgradeCodeMissing,
+        AmmError::InternalOverflowError,
+        AmmError::InternalIndexError,
+        AmmError::AmmNotFunctionalYet,
+        AmmError::TokenMetadataNotInitialised,
+        AmmError::TokenMetadataNotLoaded,
+        AmmError::TokenMetadataAlreadyLoaded,
+        AmmError::InvalidTokenBalance,
+        AmmError::InsufficientLiquidity,
+        AmmError::Slippage,
+        AmmError::PoolLocked,
+        AmmError::InvalidFeeBasisPoints,
+        AmmError::NoFlashLoanOutstanding,
+        AmmError::LimitOrderNotFound,
+        AmmError::InvalidLimitOrder,
+        AmmError::TooManyLimitOrders,
+        AmmError::AmountOutBelowMinimum,
+        AmmError::InvalidSwapFee,
+        AmmError::ReferralNotWhitelisted,
+        AmmError::InvalidDynamicFee,
+        AmmError::SwapOutputTooLarge,
+        AmmError::PriceMoveTooLarge,
+        AmmError::TradingPaused,
+        AmmError::InvalidSwapLimits,
+        AmmError::WrapNearNotSupported,
+        AmmError::InvalidSwapHistorySize,
+        AmmError::InsufficientStorageDeposit,
+        AmmError::InvalidRewardSchedule,
+        AmmError::NoRewardsToClaim,
+        AmmError::SharesBelowMinimum,
+        AmmError::InsufficientShares,
+        AmmError::LastLpRemovalInOneToken,
+        AmmError::InvalidTokenReceiverMessage,
+        AmmError::InvalidLpDepositSender,
+        AmmError::InvalidTokenTransferred,
+        AmmError::InvalidAmountTransferred,
+        AmmError::InsufficientDeposit,
+        AmmError::AccountNotRegistered,
+        AmmError::InsufficientStorageBalance,
+        AmmError::AccountHasRecords,
+    ];
+
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AmmError::OwnerCannotBeContractAccountItself => {
+                "The contract owner who is the liquidity provider cannot be the contract account itself"
+            }
+            AmmError::DuplicateTokens => "Token A and Token B cannot be the same.",
+            AmmError::CallerNotOwner => "Only the owner of this AMM can call this method.",
+            AmmError::CallerNotPendingOwner => "Only the proposed owner of this AMM can accept the ownership.",
+            AmmError::NoPendingOwner => "There is no pending ownership transfer.",
+            AmmError::SwapperCannotBeContractAccountItself => {
+                "The person who wishes to swap the token cannot be the contract account itself"
+            }
+            AmmError::CallerNotOrderOwner => "Only the account that placed this limit order can cancel it.",
+            AmmError::SwapperNotAllowed => "This account is not allowed to trade on this AMM.",
+            AmmError::PromiseTooManyResults => "Cross contract call returned more than one promise result.",
+            AmmError::PromiseWrongValueReceived => "Cross contract call returned invalid value.",
+            AmmError::PromiseCallFailed => "Cross contract call failed.",
+            AmmError::PromiseUnexpectedResultCount => {
+                "Cross contract call returned a different number of promise results than the number of tokens."
+            }
+            AmmError::StateNotInitialised => "Contract state has not been initialised.",
+            AmmError::InvalidStateVersion => "Contract state was written with an unknown layout version.",
+            AmmError::UpgradeCodeMissing => "New contract code must be attached as the raw input of upgrade.",
+            AmmError::InternalOverflowError => "There is an internal error when calculating due to overflow.",
+            AmmError::InternalIndexError => {
+                "There is an internal error when calculating due to index out of range."
+            }
+            AmmError::AmmNotFunctionalYet => {
+                "This AMM contract is not yet fully functional — most likely because liquidity has not been provided."
+            }
+            AmmError::TokenMetadataNotInitialised => "Token metadata has not been initialised.",
+            AmmError::TokenMetadataNotLoaded => {
+                "Token metadata has not been loaded yet — the owner can call refresh_metadata to retry loading it."
+            }
+            AmmError::TokenMetadataAlreadyLoaded => "Token metadata of both tokens has already been loaded.",
+            AmmError::InvalidTokenBalance => "Token balance has reached 0.",
+            AmmError::InsufficientLiquidity => "Requested amount is not less than the token balance of this AMM.",
+            AmmError::Slippage => "Token out is 0 — slippage happened!",
+            AmmError::PoolLocked => "This AMM is locked until its outstanding flash loan is repaid or its balances are reconciled.",
+            AmmError::InvalidFeeBasisPoints => "Fee cannot be more than 10000 basis points.",
+            AmmError::NoFlashLoanOutstanding => "There is no outstanding flash loan in the transferred token to repay.",
+            AmmError::LimitOrderNotFound => "There is no open limit order with this id.",
+            AmmError::InvalidLimitOrder => "Minimum amount out of a limit order cannot be zero.",
+            AmmError::TooManyLimitOrders => "This account has reached the maximum number of open limit orders.",
+            AmmError::AmountOutBelowMinimum => "Token out is less than the minimum amount out requested.",
+            AmmError::InvalidSwapFee => "Swap fee cannot be more than 1000 basis points.",
+            AmmError::ReferralNotWhitelisted => "Referrer is not whitelisted by the owner of this AMM.",
+            AmmError::InvalidDynamicFee => "Floor of the dynamic swap fee cannot be above its ceiling.",
+            AmmError::SwapOutputTooLarge => "Token out is more than the largest share of the reserve a single swap can take.",
+            AmmError::PriceMoveTooLarge => "Swap would move the price more than allowed within one block.",
+            AmmError::TradingPaused => {
+                "Trading is paused as the circuit breaker tripped — the owner can call reset_circuit_breaker to resume it."
+            }
+            AmmError::InvalidSwapLimits => {
+                "Swap limits are not set, or their output share is not between 1 and 10000 basis points or their window is empty."
+            }
+            AmmError::WrapNearNotSupported => {
+                "Native NEAR can only be swapped for the other token of a pool holding wNEAR, once the owner set it."
+            }
+            AmmError::InvalidSwapHistorySize => "Swap history cannot keep more than 1000 swaps.",
+            AmmError::InsufficientStorageDeposit => "Attached deposit does not cover the storage of the swap history.",
+            AmmError::InvalidRewardSchedule => {
+                "Reward schedule must end after it starts, be fully funded, and follow the previous schedule in the same token."
+            }
+            AmmError::NoRewardsToClaim => "This account has no rewards to claim.",
+            AmmError::SharesBelowMinimum => "LP shares minted are less than the minimum shares requested.",
+            AmmError::InsufficientShares => "LP shares of this account are less than the shares requested, or zero.",
+            AmmError::LastLpRemovalInOneToken => {
+                "The last LP cannot remove liquidity in one token, as no reserves would be left to swap against — remove it via remove_liquidity."
+            }
+            AmmError::InvalidTokenReceiverMessage => {
+                "Invalid fungible token token receiver message, should be either 'lp_deposit', 'deposit', 'swap', 'swap_to_near', 'flash_repay', a limit_order, fund_rewards or zap_in."
+            }
+            AmmError::InvalidLpDepositSender => {
+                "Only the owner of this AMM can lp_deposit, until the pool has liquidity of both tokens — add liquidity to an active pool via add_liquidity or zap_in."
+            }
+            AmmError::InvalidTokenTransferred => "Transferred token is not one of two tokens set on this AMM.",
+            AmmError::InvalidAmountTransferred => "Transferred amount cannot be zero.",
+            AmmError::InsufficientDeposit => "Deposit of this account is less than the amount requested.",
+            AmmError::AccountNotRegistered => "This account has not registered via storage_deposit.",
+            AmmError::InsufficientStorageBalance => {
+                "Storage balance of this account does not cover the storage it uses — top it up via storage_deposit."
+            }
+            AmmError::AccountHasRecords => {
+                "This account still has records such as deposits, so it cannot be unregistered without losing them."
+            }
+        }
+    }
+}
+
+// failure messages start with the code, e.g. "E506: Token out is 0 — slippage happened!", so clients can parse it
+impl fmt::Display for AmmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{}: {}", self.code(), self.message())
+    }
+}
+
+// entry of the error list returned by the contract, so SDKs can generate bindings
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ErrorCode {
+    pub code: u16,
+    pub name: String,
+    pub message: String,
+}
+
+impl From<AmmError> for ErrorCode {
+    fn from(error: AmmError) -> Self {
+        Self {
+            code: error.code(),
+            name: format!("{:?}", error),
+            message: error.message().to_string(),
+        }
+    }
+}
+
+// failures of the shared pricing maths fail with the codes of the same checks in this contract
+impl From<MathError> for AmmError {
+    fn from(error: MathError) -> Self {
+        match error {
+            MathError::Overflow => AmmError::InternalOverflowError,
+            MathError::InsufficientLiquidity => AmmError::InsufficientLiquidity,
+            MathError::InvalidFee => AmmError::InvalidFeeBasisPoints,
+        }
+    }
+}
+
+// the only way this contract fails, so every failure message carries its code
+pub fn panic_with(error: AmmError) -> ! {
+    // the mocked panic_utf8 of near-sdk 4 unwinds out of an extern "C" function, which aborts the whole test process
+    // on Rust 1.81 and later, so unit tests panic natively with the same message
+    #[cfg(test)]
+    panic!("{}", error);
+    #[cfg(not(test))]
+    near_sdk::env::panic_str(&error.to_string())
+}
+
+pub fn ensure(condition: bool, error: AmmError) {
+    if !condition {
+        panic_with(error);
+    }
+}
+
+pub trait OrPanic<T> {
+    fn or_panic(self, error: AmmError) -> T;
+}
+
+impl<T> OrPanic<T> for Option<T> {
+    fn or_panic(self, error: AmmError) -> T {
+        self.unwrap_or_else(|| panic_with(error))
+    }
+}
+
+impl<T, E> OrPanic<T> for Result<T, E> {
+    fn or_panic(self, error: AmmError) -> T {
+        self.unwrap_or_else(|_| panic_with(error))
+    }
+}
+
+// fails with the error of the result itself, e.g. of the shared pricing maths
+pub trait UnwrapOrPanic<T> {
+    fn unwrap_or_panic(self) -> T;
+}
+
+impl<T, E: Into<AmmError>> UnwrapOrPanic<T> for Result<T, E> {
+    fn unwrap_or_panic(self) -> T {
+        self.unwrap_or_else(|error| panic_with(error.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique_and_ordered() {
+        assert!(AmmError::ALL
+            .windows(2)
+            .all(|pair| pair[0].code() < pair[1].code()));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(AmmError::Slippage.to_string(), "E506: Token out is 0 — slippage happened!");
+    }
+
+    #[test]
+    #[should_panic(expected = "E102: ")]
+    fn test_ensure() {
+        ensure(true, AmmError::CallerNotPendingOwner);
+        ensure(false, AmmError::CallerNotOwner);
+    }
+
+    #[test]
+    #[should_panic(expected = "E505: ")]
+    fn test_unwrap_or_panic() {
+        assert_eq!(Ok::<_, MathError>(1).unwrap_or_panic(), 1);
+        Err::<(), _>(MathError::InsufficientLiquidity).unwrap_or_panic();
+    }
+}
@@ -0,0 +1,296 @@
+use near_amm_math::{get_fee, BASIS_POINTS_DENOMINATOR};
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env, ext_contract,
+    json_types::U128,
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance, Promise, PromiseOrValue, PromiseResult,
+};
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::{Contract, ContractExt};
+
+// interface that the receiver of a flash loan must implement, it gets called once the borrowed tokens are sent
+// and must return principal plus fee via ft_transfer_call with the flash_repay message before it resolves
+#[allow(dead_code)]
+#[ext_contract(ext_flash_loan_receiver)]
+pub trait FlashLoanReceiver {
+    fn on_flash_loan(
+        &mut self,
+        initiator: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        fee: U128,
+        msg: String,
+    ) -> PromiseOrValue<()>;
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FlashLoanMetadata {
+    initiator: AccountId,
+    receiver: AccountId,
+    token: AccountId,
+    amount: U128,
+    fee: U128,
+    repaid: U128,
+    pub shortfall: U128,
+    pub defaulted: bool,
+}
+
+// the outstanding flash loan, the pool stays locked for as long as it exists
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FlashLoan {
+    initiator: AccountId,
+    receiver: AccountId,
+    token: AccountId,
+    amount: Balance,
+    fee: Balance,
+    repaid: Balance,
+    // whether the receiver resolved without repaying in full, so the rest is owed as debt
+    defaulted: bool,
+}
+
+impl FlashLoan {
+    pub fn new(initiator: AccountId, receiver: AccountId, token: AccountId, amount: Balance, fee: Balance) -> Self {
+        Self {
+            initiator,
+            receiver,
+            token,
+            amount,
+            fee,
+            repaid: 0,
+            defaulted: false,
+        }
+    }
+
+    pub fn get_metadata(&self) -> FlashLoanMetadata {
+        FlashLoanMetadata {
+            initiator: self.initiator.clone(),
+            receiver: self.receiver.clone(),
+            token: self.token.clone(),
+            amount: U128(self.amount),
+            fee: U128(self.fee),
+            repaid: U128(self.repaid),
+            shortfall: U128(self.get_shortfall()),
+            defaulted: self.defaulted,
+        }
+    }
+
+    pub fn get_initiator(&self) -> &AccountId {
+        &self.initiator
+    }
+
+    pub fn get_receiver(&self) -> &AccountId {
+        &self.receiver
+    }
+
+    pub fn get_token(&self) -> &AccountId {
+        &self.token
+    }
+
+    pub fn get_amount(&self) -> Balance {
+        self.amount
+    }
+
+    pub fn get_fee(&self) -> Balance {
+        self.fee
+    }
+
+    // principal plus fee
+    pub fn get_owed(&self) -> Balance {
+        self.amount
+            .checked_add(self.fee)
+            .or_panic(AmmError::InternalOverflowError)
+    }
+
+    pub fn get_shortfall(&self) -> Balance {
+        self.get_owed().saturating_sub(self.repaid)
+    }
+
+    pub fn is_repaid(&self) -> bool {
+        self.get_shortfall() == 0
+    }
+
+    pub fn is_defaulted(&self) -> bool {
+        self.defaulted
+    }
+
+    pub fn set_defaulted(&mut self) {
+        self.defaulted = true;
+    }
+
+    // record a repayment, and return the part of it above what is owed so that it can be refunded
+    pub fn repay(&mut self, amount: Balance) -> Balance {
+        let accepted = amount.min(self.get_shortfall());
+        self.repaid += accepted;
+
+        amount - accepted
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the outstanding flash loan, which locks the pool until it is repaid
+    pub fn get_flash_loan(&self) -> Option<FlashLoanMetadata> {
+        self.flash_loan.as_ref().map(|flash_loan| flash_loan.get_metadata())
+    }
+
+    // public method to get the fee charged on flash loans in basis points
+    pub fn get_flash_loan_fee(&self) -> u16 {
+        self.flash_loan_fee_bps
+    }
+
+    // only contract's owner can set the fee charged on flash loans
+    pub fn set_flash_loan_fee(&mut self, fee_bps: u16) {
+        self.assert_owner();
+        ensure(fee_bps <= BASIS_POINTS_DENOMINATOR, AmmError::InvalidFeeBasisPoints);
+
+        log!("Flash loan fee set to {} basis points!", fee_bps);
+        self.flash_loan_fee_bps = fee_bps;
+    }
+
+    // borrow amount of a pool reserve within one transaction — the tokens are sent to the receiver, whose
+    // on_flash_loan gets called with msg and must repay principal plus fee via ft_transfer_call with flash_repay
+    pub fn flash_loan(&mut self, token_id: AccountId, amount: U128, receiver: AccountId, msg: String) -> Promise {
+        self.assert_unlocked();
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        ensure(amount.0 > 0, AmmError::InvalidAmountTransferred);
+
+        let fee = get_fee(amount.0, self.flash_loan_fee_bps).unwrap_or_panic();
+        let token = self
+            .tokens
+            .iter_mut()
+            .find(|token| token.check_address(&token_id))
+            .or_panic(AmmError::InvalidTokenTransferred);
+        ensure(amount.0 < token.get_balance(), AmmError::InsufficientLiquidity);
+        token.subtract_balance(amount.0);
+
+        log!(
+            "Lending {} of token {} to {} for a fee of {}!",
+            amount.0,
+            token_id,
+            receiver,
+            fee
+        );
+        self.flash_loan = Some(FlashLoan::new(
+            env::predecessor_account_id(),
+            receiver.clone(),
+            token_id.clone(),
+            amount.0,
+            fee,
+        ));
+
+        self.send_token_out(receiver, token_id, amount.0)
+            .then(Self::ext(env::current_account_id()).post_flash_loan_transfer(msg))
+    }
+
+    // callback for sending the borrowed tokens, which hands over to the receiver only if they have arrived
+    #[private]
+    pub fn post_flash_loan_transfer(&mut self, msg: String) -> PromiseOrValue<()> {
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
+        let flash_loan = self.flash_loan.as_ref().or_panic(AmmError::NoFlashLoanOutstanding);
+
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => PromiseOrValue::Promise(
+                ext_flash_loan_receiver::ext(flash_loan.get_receiver().clone())
+                    .on_flash_loan(
+                        flash_loan.get_initiator().clone(),
+                        flash_loan.get_token().clone(),
+                        U128(flash_loan.get_amount()),
+                        U128(flash_loan.get_fee()),
+                        msg,
+                    )
+                    .then(Self::ext(env::current_account_id()).post_flash_loan()),
+            ),
+            // the tokens never left the contract, so the loan is cancelled
+            PromiseResult::Failed => {
+                let flash_loan = self.flash_loan.take().or_panic(AmmError::NoFlashLoanOutstanding);
+                self.tokens
+                    .iter_mut()
+                    .find(|token| token.check_address(flash_loan.get_token()))
+                    .or_panic(AmmError::InvalidTokenTransferred)
+                    .add_balance(flash_loan.get_amount());
+                log!("Sending the flash loan to {} failed, cancelling it!", flash_loan.get_receiver());
+
+                PromiseOrValue::Value(())
+            }
+        }
+    }
+
+    // callback for the receiver's on_flash_loan, by which point principal plus fee must have been repaid
+    #[private]
+    pub fn post_flash_loan(&mut self) {
+        let mut flash_loan = self.flash_loan.take().or_panic(AmmError::NoFlashLoanOutstanding);
+        if flash_loan.is_repaid() {
+            log!("Flash loan repaid by {}, unlocking the AMM!", flash_loan.get_receiver());
+        } else {
+            // the pool stays locked until anyone repays the shortfall via flash_repay
+            log!(
+                "Flash loan to {} defaulted with a shortfall of {}, locking the AMM until it is repaid!",
+                flash_loan.get_receiver(),
+                flash_loan.get_shortfall()
+            );
+            flash_loan.set_defaulted();
+            self.flash_loan = Some(flash_loan);
+        }
+    }
+
+    // record a repayment of the outstanding flash loan, and return the part above what is owed to be refunded
+    pub(crate) fn repay_flash_loan(&mut self, token_in: &AccountId, amount: Balance) -> Balance {
+        let flash_loan = self
+            .flash_loan
+            .as_mut()
+            .filter(|flash_loan| flash_loan.get_token() == token_in)
+            .or_panic(AmmError::NoFlashLoanOutstanding);
+        let excess = flash_loan.repay(amount);
+        // a loan in progress is closed by its callback, a defaulted one is closed as soon as it is repaid
+        let settled = flash_loan.is_repaid() && flash_loan.is_defaulted();
+
+        self.tokens
+            .iter_mut()
+            .find(|token| token.check_address(token_in))
+            .or_panic(AmmError::InvalidTokenTransferred)
+            .add_balance(amount - excess);
+        log!("Flash loan repaid with {} of token {}!", amount - excess, token_in);
+
+        if settled {
+            log!("Flash loan debt settled, unlocking the AMM!");
+            self.flash_loan = None;
+        }
+
+        excess
+    }
+
+    // reserves are lent out while a flash loan is outstanding, and are being compared with the balances reported by
+    // the token contracts while sync or skim is in flight, so nothing that relies on them can run
+    pub(crate) fn is_locked(&self) -> bool {
+        self.flash_loan.is_some() || self.reconciling
+    }
+
+    pub(crate) fn assert_unlocked(&self) {
+        ensure(!self.is_locked(), AmmError::PoolLocked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+
+    #[test]
+    fn test_repay() {
+        let mut flash_loan = FlashLoan::new(accounts(0), accounts(1), accounts(2), 1_000, 9);
+        assert_eq!(flash_loan.get_owed(), 1_009);
+        assert!(!flash_loan.is_repaid());
+
+        assert_eq!(flash_loan.repay(1_000), 0);
+        assert_eq!(flash_loan.get_shortfall(), 9);
+        assert_eq!(flash_loan.repay(10), 1);
+        assert!(flash_loan.is_repaid());
+    }
+}
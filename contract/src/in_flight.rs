@@ -0,0 +1,69 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    AccountId, Balance,
+};
+
+use crate::error::*;
+use crate::StorageKey;
+
+// tokens the contract has asked a token contract to move whose callback has not run yet, e.g. a payout that may
+// still fail and be deposited back, so they count as held until the callback settles them
+// while in flight, the balances reported by the token contracts may or may not include them yet
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TransfersInFlight {
+    amounts: LookupMap<AccountId, Balance>,
+}
+
+impl TransfersInFlight {
+    pub fn new() -> Self {
+        Self {
+            amounts: LookupMap::new(StorageKey::TransfersInFlight),
+        }
+    }
+
+    pub fn get(&self, token: &AccountId) -> Balance {
+        self.amounts.get(token).unwrap_or(0)
+    }
+
+    pub fn add(&mut self, token: &AccountId, amount: Balance) {
+        let total = self
+            .get(token)
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        self.amounts.insert(token, &total);
+    }
+
+    // called by the callback of the transfer once its outcome is known, and saturating as a callback must never fail
+    // on it, which would lose the undelivered tokens it deposits
+    pub fn settle(&mut self, token: &AccountId, amount: Balance) {
+        let total = self.get(token).saturating_sub(amount);
+        if total == 0 {
+            self.amounts.remove(token);
+        } else {
+            self.amounts.insert(token, &total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_transfers_in_flight() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut in_flight = TransfersInFlight::new();
+        in_flight.add(&accounts(2), 100);
+        in_flight.add(&accounts(2), 50);
+        in_flight.settle(&accounts(2), 100);
+
+        assert_eq!(in_flight.get(&accounts(2)), 50);
+        assert_eq!(in_flight.get(&accounts(3)), 0);
+        in_flight.settle(&accounts(2), 50);
+        assert_eq!(in_flight.get(&accounts(2)), 0);
+    }
+}
@@ -1,7 +1,28 @@
+mod access_list;
+mod batch_swap;
+mod circuit_breaker;
+mod deposit;
+mod dynamic_fee;
 mod error;
+mod flash_loan;
+mod in_flight;
+mod lifecycle;
+mod limit_order;
+mod referral;
+mod rewards;
+mod shares;
+mod stats;
+mod storage;
+mod swap_history;
 mod token;
+mod upgrade;
 mod util;
+mod wrap_near;
+mod zap;
 
+use near_amm_math::{
+    get_amount_out, get_fee, get_fee_share, get_price_move_bps, get_shares_minted, quote_amount_in, quote_amount_out,
+};
 use near_contract_standards::fungible_token::{
     metadata::FungibleTokenMetadata, receiver::FungibleTokenReceiver,
 };
@@ -9,39 +30,111 @@ use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     env,
     ext_contract,
-    json_types::U128,
+    json_types::{U128, U64},
     log, near_bindgen,
     serde::{Deserialize, Serialize},
     serde_json,
     store::Vector,
     AccountId, Balance, BorshStorageKey, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
 };
-use std::fmt;
+use std::str::FromStr;
 
+use access_list::AccessList;
+use circuit_breaker::SwapLimits;
+use deposit::Deposits;
+use dynamic_fee::DynamicFee;
 use error::*;
-use token::{Token, TokenMetadata};
-use util::{amount_to_canonical_amount, canonical_amount_to_amount, FT_TRANSFER_DEPOSIT_YOCTO_NEAR, U256};
+use flash_loan::FlashLoan;
+use in_flight::TransfersInFlight;
+use lifecycle::PoolState;
+use limit_order::OrderBook;
+use token::{BalanceDifference, ObservedBalance, Token};
+pub use token::TokenMetadata;
+use referral::Referrals;
+use rewards::Rewards;
+use shares::LpShares;
+use stats::Stats;
+use storage::StorageAccounts;
+use swap_history::{SwapHistory, SwapRecord};
+use upgrade::{write_state_version, VersionedContract};
+use util::{
+    DEFAULT_FLASH_LOAN_FEE_BPS, DEFAULT_SWAP_FEE_BPS, FT_TRANSFER_DEPOSIT_YOCTO_NEAR, GAS_FOR_MIGRATE,
+    MAX_SWAP_FEE_BPS,
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct ContractMetadata {
-    owner: AccountId,
-    tokens: Vec<TokenMetadata>,
+    pub owner: AccountId,
+    pub tokens: Vec<TokenMetadata>,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub(crate) enum StorageKey {
     Token,
+    Orders,
+    OrdersByPrice,
+    OrdersByOwner,
+    OrderEscrows,
+    Deposits,
+    DepositTotals,
+    ReferralFeeShares,
+    ReferralEarnings,
+    Allowlist,
+    Denylist,
+    TokenStats,
+    Traders,
+    SwapHistory,
+    LpShares,
+    RewardAccounts,
+    TransfersInFlight,
+    StorageAccounts,
 }
 
+// once deployed, any change to this layout needs a bump of CURRENT_STATE_VERSION and a legacy struct in upgrade.rs
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     owner_address: AccountId,
+    // owner proposed by the current owner, who needs to accept the ownership before it takes effect
+    pending_owner_address: Option<AccountId>,
     // only supports two tokens
     tokens: Vector<Token>,
-    // whether the contract is ready to support swap
-    functional: bool,
+    // last swaps, as many as the owner paid the storage for
+    swap_history: SwapHistory,
+    // whether the contract is ready to accept liquidity or support swap
+    pool_state: PoolState,
+    // outstanding flash loan, which locks the contract until it is repaid
+    flash_loan: Option<FlashLoan>,
+    flash_loan_fee_bps: u16,
+    // open limit orders, whose escrowed tokens are held by the contract but are not liquidity
+    order_book: OrderBook,
+    // tokens deposited by accounts, which are held by the contract but are not liquidity either
+    deposits: Deposits,
+    // fee charged on the input of every swap, which stays in the pool except for the share of the referrer
+    swap_fee_bps: u16,
+    referrals: Referrals,
+    // replaces swap_fee_bps while set
+    dynamic_fee: Option<DynamicFee>,
+    // swaps are not limited while none
+    swap_limits: Option<SwapLimits>,
+    // who can trade
+    access_list: AccessList,
+    // pool token that is wNEAR, through which native NEAR can be swapped
+    wrap_near_id: Option<AccountId>,
+    // lifetime counters of the pool
+    stats: Stats,
+    // shares of the liquidity held by every LP
+    lp_shares: LpShares,
+    // liquidity mining rewards for the LPs
+    rewards: Rewards,
+    // tokens sent out or coming in whose callback has not run yet, which sync and skim count as held
+    transfers_in_flight: TransfersInFlight,
+    // set while sync or skim waits for the balances of the token contracts, which locks the pool like a flash loan
+    // so that no swap changes the recorded balances the reported ones get compared with
+    reconciling: bool,
+    // NEAR deposited by accounts for the storage of their records (NEP-145)
+    storage_accounts: StorageAccounts,
 }
 
 #[near_bindgen]
@@ -49,13 +142,8 @@ impl Contract {
     #[init]
     pub fn new(owner_id: AccountId, token_a_id: AccountId, token_b_id: AccountId) -> Self {
         // the contract itself holds the wallets of the tokens, so the owner must be a different account
-        assert_ne!(
-            owner_id,
-            env::current_account_id(),
-            "{}",
-            OWNER_CANNOT_BE_CONTRACT_ACCOUNT_ITSELF
-        );
-        assert_ne!(token_a_id, token_b_id, "{}", DUPLICATE_TOKENS);
+        ensure(owner_id != env::current_account_id(), AmmError::OwnerCannotBeContractAccountItself);
+        ensure(token_a_id != token_b_id, AmmError::DuplicateTokens);
 
         Self::get_token_metadata(&token_a_id);
         Self::get_token_metadata(&token_b_id);
@@ -64,27 +152,66 @@ impl Contract {
         tokens.push(Token::new(token_a_id));
         tokens.push(Token::new(token_b_id));
 
+        write_state_version();
+
         Self {
             owner_address: owner_id,
+            pending_owner_address: None,
             tokens,
-            functional: false,
+            swap_history: SwapHistory::new(),
+            pool_state: PoolState::AwaitingMetadata,
+            flash_loan: None,
+            flash_loan_fee_bps: DEFAULT_FLASH_LOAN_FEE_BPS,
+            order_book: OrderBook::new(),
+            deposits: Deposits::new(),
+            swap_fee_bps: DEFAULT_SWAP_FEE_BPS,
+            referrals: Referrals::new(),
+            dynamic_fee: None,
+            swap_limits: None,
+            access_list: AccessList::new(),
+            wrap_near_id: None,
+            stats: Stats::new(),
+            lp_shares: LpShares::new(),
+            rewards: Rewards::new(),
+            transfers_in_flight: TransfersInFlight::new(),
+            reconciling: false,
+            storage_accounts: StorageAccounts::new(),
         }
     }
 
+    // deploy new contract code passed as the raw input, only contract's owner can upgrade
+    // the state is then migrated to the layout of the new code, so no tokens or data are lost
+    pub fn upgrade(&self) -> Promise {
+        self.assert_owner();
+        let code = env::input().or_panic(AmmError::UpgradeCodeMissing);
+
+        log!("Upgrading the contract code!");
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), Vec::new(), 0, GAS_FOR_MIGRATE)
+    }
+
+    // convert the state from whichever layout it was written with to the current one
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract = VersionedContract::read().into();
+        write_state_version();
+
+        contract
+    }
+
+    // public method to get the pool's lifecycle state
+    pub fn get_pool_state(&self) -> PoolState {
+        self.pool_state
+    }
+
     // public method to get the contract's metadata
     pub fn get_metadata(&self) -> ContractMetadata {
         // metadata contains ratio which is only available after liquidity is provided
-        assert!(self.functional, "{}", AMM_NOT_FUNCTIONAL_YET);
-        let token_a_balance = self
-            .tokens
-            .get(0)
-            .expect(INTERNAL_INDEX_ERROR)
-            .get_canonical_balance() as f64;
-        let ratios: Vec<f64> = self
-            .tokens
-            .iter()
-            .map(|token| token.get_canonical_balance() as f64 / token_a_balance)
-            .collect();
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        let token_a = self.tokens.get(0).or_panic(AmmError::InternalIndexError);
+        let ratios: Vec<f64> = self.tokens.iter().map(|token| token.get_ratio_to(token_a)).collect();
         ContractMetadata {
             owner: self.owner_address.clone(),
             tokens: self
@@ -93,97 +220,274 @@ impl Contract {
                 .enumerate()
                 .map(|(index, token)| {
                     let mut metadata = token.get_metadata();
-                    metadata.ratio = *ratios.get(index).expect(INTERNAL_INDEX_ERROR);
+                    metadata.ratio = *ratios.get(index).or_panic(AmmError::InternalIndexError);
                     metadata
                 })
                 .collect(),
         }
     }
 
-    // provide liquidity, only contract's owner can provide
-    fn deposit(&mut self, token_in: AccountId, amount: Balance) {
+    // public method to get every error this contract can fail with, so SDKs can generate bindings
+    pub fn get_error_codes(&self) -> Vec<ErrorCode> {
+        AmmError::ALL.iter().map(|error| ErrorCode::from(*error)).collect()
+    }
+
+    // public method to get the current owner
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_address.clone()
+    }
+
+    // public method to get the owner proposed by the current owner, if any
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_address.clone()
+    }
+
+    // first step of ownership transfer, only contract's owner can propose a new owner
+    pub fn propose_owner(&mut self, new_owner: AccountId) {
+        self.assert_owner();
+        // the contract itself holds the wallets of the tokens, so the owner must be a different account
+        ensure(new_owner != env::current_account_id(), AmmError::OwnerCannotBeContractAccountItself);
+
+        log!("Proposing {} as the new owner!", new_owner);
+        self.pending_owner_address = Some(new_owner);
+    }
+
+    // second step of ownership transfer, the proposed owner confirms from their own account
+    pub fn accept_ownership(&mut self) {
+        let pending_owner = self
+            .pending_owner_address
+            .take()
+            .or_panic(AmmError::NoPendingOwner);
+        ensure(env::predecessor_account_id() == pending_owner, AmmError::CallerNotPendingOwner);
+
+        log!("Ownership transferred from {} to {}!", self.owner_address, pending_owner);
+        self.owner_address = pending_owner;
+    }
+
+    fn assert_owner(&self) {
+        ensure(env::predecessor_account_id() == self.owner_address, AmmError::CallerNotOwner);
+    }
+
+    // retry loading the metadata of tokens whose metadata callback failed, only contract's owner can retry
+    pub fn refresh_metadata(&mut self) {
+        self.assert_owner();
+        ensure(self.pool_state == PoolState::AwaitingMetadata, AmmError::TokenMetadataAlreadyLoaded);
+
+        self.tokens
+            .iter()
+            .filter(|token| !token.has_metadata())
+            .for_each(|token| {
+                Self::get_token_metadata(token.get_address());
+            });
+    }
+
+    // move the pool to the lifecycle state the tokens are in, which is an earlier one when a reserve is gone
+    fn update_pool_state(&mut self) {
+        let pool_state = PoolState::from_tokens(&self.tokens);
+        if pool_state != self.pool_state {
+            log!("Pool state changed from {:?} to {:?}!", self.pool_state, pool_state);
+            self.pool_state = pool_state;
+        }
+    }
+
+    // provide liquidity, minting shares for the provider once both tokens are provided
+    fn deposit(&mut self, provider_id: &AccountId, token_in: AccountId, amount: Balance) {
+        self.assert_unlocked();
+        // decimals are needed to price the liquidity, so deposits wait until metadata is loaded
+        ensure(self.pool_state != PoolState::AwaitingMetadata, AmmError::TokenMetadataNotLoaded);
+
+        let liquidity_before = self.get_liquidity();
         self.tokens
             .iter_mut()
             .find(|token| token.check_address(&token_in))
-            .expect(INVALID_TOKEN_TRANSFERRED)
+            .or_panic(AmmError::InvalidTokenTransferred)
             .add_balance(amount);
+        self.stats.record_liquidity(&token_in, amount);
+
+        let shares = get_shares_minted(self.lp_shares.get_total(), liquidity_before, self.get_liquidity())
+            .unwrap_or_panic();
+        if shares > 0 {
+            self.mint_shares(provider_id, shares);
+        }
 
         // when liquidity of both tokens are provided, the contract is functional
-        if !self.functional && self.tokens.iter().all(|token| token.get_balance() > 0) {
-            log!("Turning on the AMM engine!");
-            self.functional = true;
-        };
+        self.update_pool_state();
 
         log!("Liquidity {} of token {} added!", amount, token_in);
     }
 
-    // main swap operation
-    fn swap(&mut self, token_in_address: &AccountId, amount_in: Balance) -> (AccountId, Balance) {
+    // find the token transferred in and the other token of the pair, which is the one to be transferred out
+    fn get_token_pair(&self, token_in_address: &AccountId) -> (&Token, &Token) {
         let token_in = self.tokens
             .iter()
             .find(|token| token.check_address(token_in_address))
-            .expect(INVALID_TOKEN_TRANSFERRED);
+            .or_panic(AmmError::InvalidTokenTransferred);
         let token_out = self.tokens
             .iter()
             .find(|token| !token.check_address(token_in_address))
-            .expect(INVALID_TOKEN_TRANSFERRED);
+            .or_panic(AmmError::InvalidTokenTransferred);
 
-        let token_out_address = token_out.get_address().clone();
+        (token_in, token_out)
+    }
 
-        let canonical_balance_in = U256::from(token_in.get_canonical_balance());
-        assert!(canonical_balance_in > U256::from(0), "{}", INVALID_TOKEN_BALANCE);
+    // public method to get the fee the next swap gets charged on its input, which is the dynamic fee while it is set
+    pub fn get_swap_fee(&self) -> u16 {
+        self.dynamic_fee
+            .as_ref()
+            .map_or(self.swap_fee_bps, |dynamic_fee| dynamic_fee.get_fee())
+    }
+
+    // only contract's owner can set the fixed fee charged on swaps
+    pub fn set_swap_fee(&mut self, fee_bps: u16) {
+        self.assert_owner();
+        ensure(fee_bps <= MAX_SWAP_FEE_BPS, AmmError::InvalidSwapFee);
+
+        log!("Swap fee set to {} basis points!", fee_bps);
+        self.swap_fee_bps = fee_bps;
+    }
+
+    // public method to quote how much of the other token a swap of amount_in would give after the fee, rounded down
+    pub fn get_amount_out(&self, token_in: AccountId, amount_in: U128) -> U128 {
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        let (token_in, token_out) = self.get_token_pair(&token_in);
+
+        U128(
+            quote_amount_out(amount_in.0, token_in.get_balance(), token_out.get_balance(), self.get_swap_fee())
+                .unwrap_or_panic(),
+        )
+    }
+
+    // public method to quote how much of the other token needs to be swapped to get amount_out including the fee,
+    // rounded up
+    pub fn get_amount_in(&self, token_out: AccountId, amount_out: U128) -> U128 {
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        let (token_out, token_in) = self.get_token_pair(&token_out);
 
-        let canonical_balance_out = U256::from(token_out.get_canonical_balance());
-        assert!(canonical_balance_out > U256::from(0), "{}", INVALID_TOKEN_BALANCE);
+        U128(
+            quote_amount_in(amount_out.0, token_in.get_balance(), token_out.get_balance(), self.get_swap_fee())
+                .unwrap_or_panic(),
+        )
+    }
+
+    // main swap operation, the referrer if any must be whitelisted and gets their share of the fee as a deposit
+    // a swap that cannot happen, e.g. one too small to get anything out or one that would break the swap limits,
+    // leaves the pool untouched and returns why, so the caller can refund it instead of failing, which matters in
+    // callbacks where failing would lose the tokens
+    fn swap(
+        &mut self,
+        trader_id: &AccountId,
+        token_in_address: &AccountId,
+        amount_in: Balance,
+        referral_id: Option<&AccountId>,
+    ) -> Result<(AccountId, Balance), AmmError> {
+        self.assert_unlocked();
+        let (token_in, token_out) = self.get_token_pair(token_in_address);
+        let token_out_address = token_out.get_address().clone();
+
+        let balance_in = token_in.get_balance();
+        let balance_out = token_out.get_balance();
+        if balance_in == 0 || balance_out == 0 {
+            return Err(AmmError::InvalidTokenBalance);
+        }
 
         log!(
             "In token {}'s balance before swap: {}",
             token_in_address,
-            canonical_balance_in, 
+            balance_in,
         );
 
         log!(
             "Out token {}'s balance before swap: {}",
             token_out_address,
-            canonical_balance_out, 
+            balance_out,
         );
 
-        let canonical_amount_in = U256::from(
-            amount_to_canonical_amount(amount_in, token_in.get_decimal())
-        );
+        let fee_bps = self.get_swap_fee();
+        let fee = get_fee(amount_in, fee_bps)?;
+        let referral_fee = referral_id.map_or(0, |referral_id| {
+            let fee_share_bps = self
+                .referrals
+                .get_fee_share(referral_id)
+                .or_panic(AmmError::ReferralNotWhitelisted);
+            get_fee_share(fee, fee_share_bps).unwrap_or_panic()
+        });
+        log!("Swap fee: {} at {} basis points, of which the referrer gets {}", fee, fee_bps, referral_fee);
 
-        // x * y = k constant product market making formula
-        let canonical_amount_out = (canonical_amount_in * canonical_balance_out / (canonical_balance_in + canonical_amount_in)).as_u128();
+        // rounded down in the pool's favour, so k never decreases
+        let amount_out = get_amount_out(amount_in - fee, balance_in, balance_out);
+        if amount_out == 0 {
+            return Err(AmmError::Slippage);
+        }
 
-        let amount_out = canonical_amount_to_amount(
-            canonical_amount_out,
-            token_out.get_decimal()
+        let price_move_bps = get_price_move_bps(
+            (balance_in, balance_out),
+            (balance_in + amount_in - referral_fee, balance_out - amount_out),
         );
-        assert!(amount_out > 0, "{}", SLIPPAGE);
+        if let Some(swap_limits) = self.swap_limits.as_ref() {
+            swap_limits.check(amount_out, balance_out, price_move_bps)?;
+        }
 
         self.tokens
             .iter_mut()
             .for_each(|token| {
                 if token.check_address(token_in_address) {
-                    token.add_balance(amount_in);
+                    // the fee stays in the pool, so the liquidity earns it
+                    token.add_balance(amount_in - referral_fee);
                     log!(
                         "In token {}'s balance after swap: {}",
                         token_in_address,
-                        token.get_canonical_balance(),
+                        token.get_balance(),
                     )
                 } else if token.check_address(&token_out_address) {
                     token.subtract_balance(amount_out);
                     log!(
                         "Out token {}'s balance after swap: {}",
                         token_out_address,
-                        token.get_canonical_balance(),
+                        token.get_balance(),
                     )
                 } else {
                     unreachable!()
                 }
             });
 
-        (token_out_address, amount_out)
+        // the fee of later swaps rises with how far this swap moved the price
+        if let Some(dynamic_fee) = self.dynamic_fee.as_mut() {
+            dynamic_fee.record_price_move(price_move_bps);
+        }
+        if let Some(swap_limits) = self.swap_limits.as_mut() {
+            swap_limits.record_price_move(price_move_bps);
+        }
+
+        log!(
+            "Swap by {} of {} token {} for {} token {}!",
+            trader_id,
+            amount_in,
+            token_in_address,
+            amount_out,
+            token_out_address,
+        );
+        self.record_trader(trader_id);
+        self.stats.record_swap(
+            (token_in_address, amount_in),
+            (&token_out_address, amount_out),
+            fee - referral_fee,
+        );
+        self.swap_history.record(SwapRecord {
+            sender_id: trader_id.clone(),
+            token_in: token_in_address.clone(),
+            token_out: token_out_address.clone(),
+            amount_in: U128(amount_in),
+            amount_out: U128(amount_out),
+            fee: U128(fee),
+            timestamp: U64(env::block_timestamp()),
+        });
+
+        if let Some(referral_id) = referral_id.filter(|_| referral_fee > 0) {
+            self.credit_owed_deposit(referral_id, token_in_address, referral_fee);
+            self.referrals.add_earning(referral_id, token_in_address, referral_fee);
+        }
+
+        Ok((token_out_address, amount_out))
     }
 
     // cross-contract call to send swapped token to user, if this operation fails the token will be refunded to the contract's account
@@ -198,6 +502,195 @@ impl Contract {
             )
     }
 
+    // send swapped token to the receiver, with ft_transfer_call when a message for the receiver is given so it gets notified
+    // whatever does not arrive is refunded to the contract's account, and is then deposited for the sender to withdraw
+    fn deliver_token_out(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        token_id: AccountId,
+        amount: Balance,
+        receiver_msg: Option<String>,
+    ) -> Promise {
+        self.transfers_in_flight.add(&token_id, amount);
+        let transfer = ext_fungible_token::ext(token_id.clone()).with_attached_deposit(FT_TRANSFER_DEPOSIT_YOCTO_NEAR);
+        let transfer = match receiver_msg {
+            Some(receiver_msg) => transfer.ft_transfer_call(receiver_id, U128(amount), None, receiver_msg),
+            None => transfer.ft_transfer(receiver_id, U128(amount), None),
+        };
+
+        transfer.then(Self::ext(env::current_account_id()).post_deliver_token_out(sender_id, token_id, U128(amount)))
+    }
+
+    // callback for deliver_token_out, ft_transfer returns nothing while ft_transfer_call returns the amount used
+    #[private]
+    pub fn post_deliver_token_out(&mut self, sender_id: AccountId, token_id: AccountId, amount: U128) {
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
+        let undelivered = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) if value.is_empty() => 0,
+            PromiseResult::Successful(value) => {
+                let used = serde_json::from_slice::<U128>(&value).or_panic(AmmError::PromiseWrongValueReceived);
+                amount.0.saturating_sub(used.0)
+            }
+            PromiseResult::Failed => amount.0,
+        };
+
+        self.transfers_in_flight.settle(&token_id, amount.0);
+        if undelivered > 0 {
+            log!("{} of token {} was not delivered, depositing it for {}!", undelivered, token_id, sender_id);
+            self.credit_owed_deposit(&sender_id, &token_id, undelivered);
+        }
+    }
+
+    // public method to get the difference between the recorded balance of each token and its balance as last
+    // reported by the token contract, call observe_balances beforehand to get an up-to-date report
+    pub fn get_balance_differences(&self) -> Vec<BalanceDifference> {
+        self.tokens
+            .iter()
+            .map(|token| token.get_balance_difference(self.get_held_for_accounts(token.get_address())))
+            .collect()
+    }
+
+    // record the balances reported by the token contracts, callable by anyone as it only records facts
+    pub fn observe_balances(&self) -> Promise {
+        self.get_token_balances()
+            .then(Self::ext(env::current_account_id()).post_observe_balances())
+    }
+
+    // set the recorded balances to the balances reported by the token contracts less what is held for accounts,
+    // only contract's owner can sync
+    // the pool is locked until the callback, and tokens still moving from earlier calls count as held, so the
+    // recorded balances are never set above what the contract holds
+    pub fn sync(&mut self) -> Promise {
+        self.assert_owner();
+        self.assert_unlocked();
+        self.reconciling = true;
+        self.get_token_balances()
+            .then(Self::ext(env::current_account_id()).post_sync())
+    }
+
+    // send the balances reported by the token contracts above the recorded balances to the given account,
+    // e.g. tokens transferred to the contract directly or refunded by a failed payout, only contract's owner can skim
+    // locked the same way as sync, and whatever cannot be delivered is deposited for the account to withdraw
+    pub fn skim(&mut self, to: AccountId) -> Promise {
+        self.assert_owner();
+        self.assert_unlocked();
+        self.reconciling = true;
+        self.get_token_balances()
+            .then(Self::ext(env::current_account_id()).post_skim(to))
+    }
+
+    // tokens held by the contract that are not liquidity, i.e. escrowed for limit orders, deposited by accounts,
+    // held for rewards or still moving
+    fn get_held_for_accounts(&self, token: &AccountId) -> Balance {
+        self.order_book.get_escrowed(token)
+            + self.deposits.get_total(token)
+            + self.rewards.get_held(token)
+            + self.transfers_in_flight.get(token)
+    }
+
+    // cross-contract calls to get the balance of the contract from every token contract, joined in token order
+    fn get_token_balances(&self) -> Promise {
+        let mut promises = self.tokens.iter().map(|token| {
+            ext_fungible_token::ext(token.get_address().clone())
+                .ft_balance_of(env::current_account_id())
+        });
+        let first = promises.next().or_panic(AmmError::InternalIndexError);
+
+        promises.fold(first, |joined, promise| joined.and(promise))
+    }
+
+    // read the balances returned to a callback of get_token_balances, and record them on the tokens
+    fn record_observed_balances(&mut self) -> Result<Vec<Balance>, AmmError> {
+        if env::promise_results_count() != u64::from(self.tokens.len()) {
+            return Err(AmmError::PromiseUnexpectedResultCount);
+        }
+
+        let block_height = env::block_height();
+        self.tokens
+            .iter_mut()
+            .enumerate()
+            .map(|(index, token)| {
+                let amount = match env::promise_result(index as u64) {
+                    PromiseResult::NotReady => unreachable!(),
+                    PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                        .map_err(|_| AmmError::PromiseWrongValueReceived)?
+                        .0,
+                    PromiseResult::Failed => return Err(AmmError::PromiseCallFailed),
+                };
+                log!(
+                    "Token {} reported a balance of {} against the recorded {}!",
+                    token.get_address(),
+                    amount,
+                    token.get_balance()
+                );
+                token.set_observed_balance(ObservedBalance { amount, block_height });
+
+                Ok(amount)
+            })
+            .collect()
+    }
+
+    // callback for the balance cross-contract calls of observe_balances
+    #[private]
+    pub fn post_observe_balances(&mut self) {
+        self.record_observed_balances().unwrap_or_panic();
+    }
+
+    // callback for the balance cross-contract calls of sync
+    #[private]
+    pub fn post_sync(&mut self) {
+        // unlocked before anything can fail, as a failed callback would leave the pool locked for good
+        self.reconciling = false;
+        let balances = match self.record_observed_balances() {
+            Ok(balances) => balances,
+            Err(error) => {
+                log!("Sync aborted: {}", error);
+                return;
+            }
+        };
+        let held_for_accounts: Vec<Balance> = self
+            .tokens
+            .iter()
+            .map(|token| self.get_held_for_accounts(token.get_address()))
+            .collect();
+        self.tokens
+            .iter_mut()
+            .zip(balances)
+            .zip(held_for_accounts)
+            .for_each(|((token, balance), held)| token.set_balance(balance.saturating_sub(held)));
+
+        self.update_pool_state();
+    }
+
+    // callback for the balance cross-contract calls of skim
+    #[private]
+    pub fn post_skim(&mut self, to: AccountId) {
+        self.reconciling = false;
+        let balances = match self.record_observed_balances() {
+            Ok(balances) => balances,
+            Err(error) => {
+                log!("Skim aborted: {}", error);
+                return;
+            }
+        };
+        let surpluses: Vec<(AccountId, Balance)> = self
+            .tokens
+            .iter()
+            .zip(balances)
+            .map(|(token, balance)| {
+                let held = token.get_balance() + self.get_held_for_accounts(token.get_address());
+                (token.get_address().clone(), balance.saturating_sub(held))
+            })
+            .filter(|(_, surplus)| *surplus > 0)
+            .collect();
+        surpluses.into_iter().for_each(|(token_id, surplus)| {
+            log!("Skimming {} of token {} to {}!", surplus, token_id, to);
+            self.deliver_token_out(to.clone(), to.clone(), token_id, surplus, None);
+        });
+    }
+
     // cross-contract call to get token metadata
     fn get_token_metadata(token_id: &AccountId) -> Promise {
         ext_fungible_token::ext(token_id.clone())
@@ -208,12 +701,7 @@ impl Contract {
     // callback for the token metadata cross-contract call above
     #[private]
     pub fn post_fungible_token_metadata(&mut self, token_id: &AccountId) {
-        assert_eq!(
-            env::promise_results_count(),
-            1,
-            "{}",
-            PROMISE_TOO_MANY_RESULTS
-        );
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
         log!(
             "Received callback from {}'s ft_metadata cross contract call!",
             token_id
@@ -225,44 +713,77 @@ impl Contract {
                     self.tokens
                         .iter_mut()
                         .find(|token| token.check_address(token_id))
-                        .expect(PROMISE_WRONG_VALUE_RECEIVED)
+                        .or_panic(AmmError::PromiseWrongValueReceived)
                         .set_metadata(metadata);
+                    self.update_pool_state();
                 } else {
-                    env::panic_str(PROMISE_WRONG_VALUE_RECEIVED);
+                    panic_with(AmmError::PromiseWrongValueReceived);
                 }
             }
-            PromiseResult::Failed => env::panic_str(PROMISE_CALL_FAILED),
+            PromiseResult::Failed => panic_with(AmmError::PromiseCallFailed),
         }
     }
 }
 
 // interface for cross-contract call
+#[allow(dead_code)]
 #[ext_contract(ext_fungible_token)]
 trait FungibleToken {
     fn ft_metadata(&self) -> FungibleTokenMetadata;
 
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+
     fn ft_transfer(
         &mut self,
         receiver_id: AccountId,
         amount: U128,
         memo: Option<String>,
     );
+
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> U128;
 }
 
-// message attached in payload that gets sent when this contract receives a token 
-#[derive(Serialize, Deserialize)]
-#[serde(crate = "near_sdk::serde")]
-enum FungibleTokenReceiverMessage {
+// message attached in payload that gets sent when this contract receives a token
+// messages are JSON, e.g. {"limit_order":{"min_amount_out":"1"}}, or plain words for messages whose arguments are all
+// optional, e.g. "swap"
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum FungibleTokenReceiverMessage {
+    #[serde(rename = "lp_deposit")]
     LPDeposit,
-    Swap,
+    Deposit,
+    // the output goes to the sender when no receiver is given, and is sent with ft_transfer_call when a message is given
+    Swap {
+        receiver_id: Option<AccountId>,
+        receiver_msg: Option<String>,
+        // must be whitelisted by the owner, and gets a share of the swap fee
+        referral_id: Option<AccountId>,
+        // the whole swap fails and is refunded when it would give less
+        min_amount_out: Option<U128>,
+    },
+    // swap into wNEAR, which is then unwrapped and sent to the sender as native NEAR
+    SwapToNear,
+    FlashRepay,
+    LimitOrder { min_amount_out: U128 },
+    // funds a reward schedule for the LPs, only the owner can fund it and any token can be the reward
+    FundRewards { start: U64, end: U64, rate: U128 },
+    // provides liquidity from one token, swapping part of it for the other token first
+    ZapIn { min_shares: U128 },
 }
 
-impl fmt::Display for FungibleTokenReceiverMessage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            FungibleTokenReceiverMessage::LPDeposit => write!(f, "lp_deposit"),
-            FungibleTokenReceiverMessage::Swap => write!(f, "swap"),
-        }
+impl FromStr for FungibleTokenReceiverMessage {
+    type Err = serde_json::Error;
+
+    fn from_str(msg: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(msg)
+            .or_else(|_| serde_json::from_value(serde_json::Value::String(msg.to_string())))
+            .or_else(|_| serde_json::from_value(serde_json::json!({ msg: {} })))
     }
 }
 
@@ -275,53 +796,153 @@ impl FungibleTokenReceiver for Contract {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        assert!(amount > U128(0), "{}", INVALID_AMOUNT_TRANSFERRED);
+        ensure(amount > U128(0), AmmError::InvalidAmountTransferred);
 
         let token_in = env::predecessor_account_id();
         log!("Received {} token {} from {} with msg {}!", amount.0, token_in, sender_id, msg);
 
-        assert!(
-            self.tokens
-                .iter()
-                .any(|token| token.check_address(&token_in)),
-            "{}",
-            INVALID_TOKEN_TRANSFERRED,
+        let message = msg.parse().or_panic(AmmError::InvalidTokenReceiverMessage);
+        ensure(
+            matches!(message, FungibleTokenReceiverMessage::FundRewards { .. })
+                || self.tokens.iter().any(|token| token.check_address(&token_in)),
+            AmmError::InvalidTokenTransferred,
         );
 
-        if msg == FungibleTokenReceiverMessage::LPDeposit.to_string() {
-            assert_eq!(
-                sender_id, self.owner_address,
-                "{}",
-                INVALID_LP_DEPOSIT_SENDER
-            );
-            self.deposit(token_in, amount.0);
+        match message {
+            FungibleTokenReceiverMessage::LPDeposit => {
+                // only seeds the pool, as a single token added to an active pool would move the price without a swap,
+                // i.e. without paying the swap fee, so later liquidity goes through add_liquidity or zap_in
+                ensure(
+                    self.pool_state != PoolState::Active && sender_id == self.owner_address,
+                    AmmError::InvalidLpDepositSender,
+                );
+                self.deposit(&sender_id, token_in, amount.0);
 
-            PromiseOrValue::Value(U128(0))
-        } else if msg == FungibleTokenReceiverMessage::Swap.to_string() {
-            assert!(self.functional, "{}", AMM_NOT_FUNCTIONAL_YET);
-            assert_ne!(
-                sender_id,
-                env::current_account_id(),
-                "{}",
-                SWAPPER_CANNOT_BE_CONTRACT_ACCOUNT_ITSELF
-            );
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::Deposit => {
+                self.credit_deposit(&sender_id, &token_in, amount.0);
+                log!("Deposit {} of token {} added for {}!", amount.0, token_in, sender_id);
+
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::Swap { receiver_id, receiver_msg, referral_id, min_amount_out } => {
+                ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+                ensure(
+                    sender_id != env::current_account_id(),
+                    AmmError::SwapperCannotBeContractAccountItself,
+                );
+                if !self.access_list.is_allowed(&sender_id) {
+                    log!("Swap refunded: {}", AmmError::SwapperNotAllowed);
+                    return PromiseOrValue::Value(amount);
+                }
+
+                let (token_out, amount_out) = match self.swap(&sender_id, &token_in, amount.0, referral_id.as_ref()) {
+                    Ok(swapped) => swapped,
+                    // the whole amount is refunded by the token contract
+                    Err(error) => {
+                        log!("Swap refunded: {}", error);
+                        return PromiseOrValue::Value(amount);
+                    }
+                };
+                // failing reverts the swap, and the token contract refunds the whole amount
+                ensure(
+                    amount_out >= min_amount_out.map_or(0, |min_amount_out| min_amount_out.0),
+                    AmmError::AmountOutBelowMinimum,
+                );
+                let receiver_id = receiver_id.unwrap_or_else(|| sender_id.clone());
+                self.deliver_token_out(sender_id, receiver_id, token_out.clone(), amount_out, receiver_msg);
+                // the swap raised the price of token_out, which may have crossed the limit of orders selling it
+                self.fill_crossed_orders(&token_out);
+
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::SwapToNear => {
+                ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+                ensure(
+                    sender_id != env::current_account_id(),
+                    AmmError::SwapperCannotBeContractAccountItself,
+                );
+                let wrap_near_id = self.wrap_near_id.clone().or_panic(AmmError::WrapNearNotSupported);
+                ensure(token_in != wrap_near_id, AmmError::WrapNearNotSupported);
+                if !self.access_list.is_allowed(&sender_id) {
+                    log!("Swap refunded: {}", AmmError::SwapperNotAllowed);
+                    return PromiseOrValue::Value(amount);
+                }
+
+                let (token_out, amount_out) = match self.swap(&sender_id, &token_in, amount.0, None) {
+                    Ok(swapped) => swapped,
+                    Err(error) => {
+                        log!("Swap refunded: {}", error);
+                        return PromiseOrValue::Value(amount);
+                    }
+                };
+                self.send_near_out(sender_id, amount_out);
+                self.fill_crossed_orders(&token_out);
+
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::FlashRepay => {
+                // anything above what is owed is refunded to the sender
+                let excess = self.repay_flash_loan(&token_in, amount.0);
+
+                PromiseOrValue::Value(U128(excess))
+            }
+            FungibleTokenReceiverMessage::LimitOrder { min_amount_out } => {
+                ensure(
+                    sender_id != env::current_account_id(),
+                    AmmError::SwapperCannotBeContractAccountItself,
+                );
+                if !self.access_list.is_allowed(&sender_id) {
+                    log!("Limit order refunded: {}", AmmError::SwapperNotAllowed);
+                    return PromiseOrValue::Value(amount);
+                }
+                self.place_order(sender_id, token_in, amount.0, min_amount_out.0);
+
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::ZapIn { min_shares } => {
+                ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+                ensure(
+                    sender_id != env::current_account_id(),
+                    AmmError::SwapperCannotBeContractAccountItself,
+                );
+                if !self.access_list.is_allowed(&sender_id) {
+                    log!("Zap refunded: {}", AmmError::SwapperNotAllowed);
+                    return PromiseOrValue::Value(amount);
+                }
+
+                if let Err(error) = self.zap_in(&sender_id, &token_in, amount.0, min_shares.0) {
+                    log!("Zap refunded: {}", error);
+                    return PromiseOrValue::Value(amount);
+                }
+                let token_out = self.get_token_pair(&token_in).1.get_address().clone();
+                self.fill_crossed_orders(&token_out);
 
-            let (token_out, amount_out) = self.swap(&token_in, amount.0);
-            self.send_token_out(sender_id, token_out, amount_out);
+                PromiseOrValue::Value(U128(0))
+            }
+            FungibleTokenReceiverMessage::FundRewards { start, end, rate } => {
+                ensure(sender_id == self.owner_address, AmmError::CallerNotOwner);
+                // anything above what the schedule pays out is refunded to the owner
+                let excess = self.fund_rewards(&token_in, amount.0, start.0, end.0, rate.0);
 
-            PromiseOrValue::Value(U128(0))
-        } else {
-            env::panic_str(INVALID_TOKEN_RECEIVER_MESSAGE);
+                PromiseOrValue::Value(U128(excess))
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::testing_env;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U64;
+    use near_sdk::test_utils::{accounts, get_created_receipts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+    use proptest::prelude::*;
 
     use super::*;
+    use crate::util::{DEPOSIT_RECORD_STORAGE_BYTES, ORDER_RECORD_STORAGE_BYTES, TRADER_RECORD_STORAGE_BYTES};
+    use near_amm_math::U256;
 
     #[test]
     fn test_new() {
@@ -344,9 +965,10 @@ mod tests {
         );
 
         assert_eq!(contract.owner_address, owner);
+        assert_eq!(contract.pending_owner_address, None);
         assert_eq!(contract.tokens[0].get_address().as_str(), token_a_address.as_str());
         assert_eq!(contract.tokens[1].get_address().as_str(), token_b_address.as_str());
-        assert!(!contract.functional);
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingMetadata);
     }
 
     #[test]
@@ -414,4 +1036,1245 @@ mod tests {
 
         contract.get_metadata();
     }
+
+    #[test]
+    fn test_get_error_codes() {
+        let contract = active_contract((8, 6), (1_000, 1_000));
+        let error_codes = contract.get_error_codes();
+        assert_eq!(error_codes.len(), AmmError::ALL.len());
+        assert_eq!(error_codes[0].code, 100);
+        assert_eq!(error_codes[0].name, "OwnerCannotBeContractAccountItself");
+    }
+
+    #[test]
+    fn test_transfer_ownership() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+        let token_a_address = accounts(2);
+        let token_b_address = accounts(3);
+        let new_owner = accounts(4);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(owner.clone())
+            .predecessor_account_id(owner.clone());
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(
+            owner.clone(),
+            token_a_address.clone(),
+            token_b_address.clone(),
+        );
+
+        contract.propose_owner(new_owner.clone());
+        assert_eq!(contract.get_owner(), owner);
+        assert_eq!(contract.get_pending_owner(), Some(new_owner.clone()));
+
+        testing_env!(context
+            .signer_account_id(new_owner.clone())
+            .predecessor_account_id(new_owner.clone())
+            .build());
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner(), new_owner);
+        assert_eq!(contract.get_pending_owner(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_propose_owner() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+        let token_a_address = accounts(2);
+        let token_b_address = accounts(3);
+        let new_owner = accounts(4);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(new_owner.clone())
+            .predecessor_account_id(new_owner.clone());
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(
+            owner.clone(),
+            token_a_address.clone(),
+            token_b_address.clone(),
+        );
+
+        contract.propose_owner(new_owner.clone());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_accept_ownership() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+        let token_a_address = accounts(2);
+        let token_b_address = accounts(3);
+        let new_owner = accounts(4);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(owner.clone())
+            .predecessor_account_id(owner.clone());
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(
+            owner.clone(),
+            token_a_address.clone(),
+            token_b_address.clone(),
+        );
+
+        contract.propose_owner(new_owner.clone());
+        contract.accept_ownership();
+    }
+
+    fn token_metadata_result(decimals: u8) -> PromiseResult {
+        PromiseResult::Successful(
+            serde_json::to_vec(&FungibleTokenMetadata {
+                spec: "ft-1.0.0".to_string(),
+                name: "test-name".to_string(),
+                symbol: "TST".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals,
+            })
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_pool_lifecycle() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+        let token_a_address = accounts(2);
+        let token_b_address = accounts(3);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(owner.clone())
+            .predecessor_account_id(owner.clone());
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(
+            owner.clone(),
+            token_a_address.clone(),
+            token_b_address.clone(),
+        );
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingMetadata);
+
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![token_metadata_result(8)],
+        );
+        contract.post_fungible_token_metadata(&token_a_address);
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingMetadata);
+
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![token_metadata_result(6)],
+        );
+        contract.post_fungible_token_metadata(&token_b_address);
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingLiquidity);
+
+        contract.deposit(&accounts(1), token_a_address.clone(), 100);
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingLiquidity);
+        contract.deposit(&accounts(1), token_b_address.clone(), 10);
+        assert_eq!(contract.get_pool_state(), PoolState::Active);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_deposit_before_metadata() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+        let token_a_address = accounts(2);
+        let token_b_address = accounts(3);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(owner.clone())
+            .predecessor_account_id(owner.clone());
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(
+            owner.clone(),
+            token_a_address.clone(),
+            token_b_address.clone(),
+        );
+
+        contract.deposit(&accounts(1), token_a_address.clone(), 100);
+    }
+
+    // contract whose metadata callbacks have run and whose liquidity has been provided by the owner
+    fn active_contract(decimals: (u8, u8), balances: (Balance, Balance)) -> Contract {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(accounts(0))
+            .signer_account_id(accounts(1))
+            .predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+
+        let mut contract = Contract::new(accounts(1), accounts(2), accounts(3));
+        for (token_address, decimal) in [(accounts(2), decimals.0), (accounts(3), decimals.1)] {
+            testing_env!(
+                context.build(),
+                VMConfig::test(),
+                RuntimeFeesConfig::test(),
+                Default::default(),
+                vec![token_metadata_result(decimal)],
+            );
+            contract.post_fungible_token_metadata(&token_address);
+        }
+        contract.deposit(&accounts(1), accounts(2), balances.0);
+        contract.deposit(&accounts(1), accounts(3), balances.1);
+
+        contract
+    }
+
+    fn product(contract: &Contract) -> U256 {
+        contract
+            .tokens
+            .iter()
+            .fold(U256::one(), |product, token| product * U256::from(token.get_balance()))
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        assert_eq!(contract.get_amount_out(accounts(2), U128(100_000)), U128(90_909));
+        assert_eq!(contract.get_amount_in(accounts(3), U128(90_909)), U128(100_000));
+
+        let (token_out, amount_out) = contract.swap(&accounts(4), &accounts(2), 100_000, None).unwrap();
+        assert_eq!(token_out, accounts(3));
+        assert_eq!(amount_out, 90_909);
+        assert_eq!(contract.tokens[0].get_balance(), 1_100_000);
+        assert_eq!(contract.tokens[1].get_balance(), 909_091);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_swap_slippage() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000));
+        contract.swap(&accounts(4), &accounts(2), 1, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "E513: ")]
+    fn test_swap_below_min_amount_out() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(2), vec![]);
+        // 100 of token A only gets 90 of token B
+        contract.ft_on_transfer(accounts(4), U128(100), r#"{"swap":{"min_amount_out":"91"}}"#.to_string());
+    }
+
+    #[test]
+    fn test_get_metadata_ratio() {
+        // 10 tokens of 8 decimals against 1 token of 6 decimals
+        let contract = active_contract((8, 6), (1_000_000_000, 1_000_000));
+        let tokens = contract.get_metadata().tokens;
+        assert_eq!(tokens[0].ratio, 1f64);
+        assert_eq!(tokens[1].ratio, 0.1);
+    }
+
+    #[test]
+    fn test_get_metadata_ratio_of_many_decimals() {
+        // balances that scale down to nothing at the canonical decimal still have a ratio, which stays finite
+        for decimals in [(30, 6), (6, 30), (u8::MAX, 0), (0, u8::MAX)] {
+            let contract = active_contract(decimals, (1, Balance::MAX));
+            let ratio = contract.get_metadata().tokens[1].ratio;
+            assert!(ratio.is_finite() && ratio > 0f64, "{:?}", decimals);
+        }
+    }
+
+    // quotes and swaps give exactly the golden results of near-amm-math, which off-chain code prices with
+    #[test]
+    fn test_golden_quotes() {
+        for line in include_str!("../../math/golden.txt").lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields[0] != "amount_out" && fields[0] != "amount_in" {
+                continue;
+            }
+            let amount = |index: usize| fields[index].parse::<Balance>().unwrap();
+            let mut contract = active_contract((8, 6), (amount(2), amount(3)));
+            contract.set_swap_fee(fields[4].parse().unwrap());
+
+            if fields[0] == "amount_in" {
+                assert_eq!(contract.get_amount_in(accounts(3), U128(amount(1))), U128(amount(5)), "{}", line);
+                continue;
+            }
+            assert_eq!(contract.get_amount_out(accounts(2), U128(amount(1))), U128(amount(5)), "{}", line);
+            // swaps that would give nothing out are rejected as slippage
+            if amount(5) > 0 {
+                let (_, amount_out) = contract.swap(&accounts(4), &accounts(2), amount(1), None).unwrap();
+                assert_eq!(amount_out, amount(5), "{}", line);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_swap_never_decreases_k(
+            decimals in (0u8..=u8::MAX, 0u8..=u8::MAX),
+            balances in (1u128..=u64::MAX as u128, 1u128..=u64::MAX as u128),
+            amounts_in in proptest::collection::vec((any::<bool>(), 1u128..=u64::MAX as u128), 1..10),
+        ) {
+            let mut contract = active_contract(decimals, balances);
+            for (swap_a_for_b, amount_in) in amounts_in {
+                let token_in = if swap_a_for_b { accounts(2) } else { accounts(3) };
+                // swaps that would give nothing out are rejected as slippage, so skip them
+                if contract.get_amount_out(token_in.clone(), U128(amount_in)) == U128(0) {
+                    continue;
+                }
+
+                let k_before = product(&contract);
+                contract.swap(&accounts(4), &token_in, amount_in, None).unwrap();
+                prop_assert!(product(&contract) >= k_before);
+            }
+        }
+    }
+
+    fn balance_results(balances: &[Balance]) -> Vec<PromiseResult> {
+        balances
+            .iter()
+            .map(|balance| PromiseResult::Successful(serde_json::to_vec(&U128(*balance)).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(accounts(0))
+                .block_index(42)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            balance_results(&[1_200, 900]),
+        );
+
+        contract.post_sync();
+        assert_eq!(contract.tokens[0].get_balance(), 1_200);
+        assert_eq!(contract.tokens[1].get_balance(), 900);
+        let differences = contract.get_balance_differences();
+        assert_eq!(differences[0].surplus, U128(0));
+        assert_eq!(differences[1].deficit, U128(0));
+        assert_eq!(differences[1].observed_block_height, Some(U64(42)));
+    }
+
+    #[test]
+    fn test_sync_of_emptied_reserve() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(0), balance_results(&[1_000, 0]));
+
+        // the pool goes back to waiting for liquidity rather than pricing swaps against an empty reserve
+        contract.post_sync();
+        assert_eq!(contract.get_pool_state(), PoolState::AwaitingLiquidity);
+    }
+
+    #[test]
+    fn test_skim() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(accounts(0))
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            balance_results(&[1_200, 900]),
+        );
+
+        contract.post_skim(accounts(4));
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+        assert_eq!(contract.tokens[1].get_balance(), 1_000);
+        let differences = contract.get_balance_differences();
+        // the skimmed surplus counts as held while it is being sent out
+        assert_eq!(differences[0].surplus, U128(0));
+        assert_eq!(differences[1].deficit, U128(100));
+        // only the surplus of the first token is sent out, followed by its callback
+        assert_eq!(get_created_receipts().len(), 2);
+        assert_eq!(get_created_receipts()[0].receiver_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_sync() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(4))
+            .build());
+
+        contract.sync();
+    }
+
+    #[test]
+    #[should_panic(expected = "E507: ")]
+    fn test_swap_while_syncing() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        contract.sync();
+
+        // a swap would change the recorded balances the reported ones are about to overwrite
+        contract.swap(&accounts(4), &accounts(2), 100, None).unwrap();
+    }
+
+    #[test]
+    fn test_failed_sync_unlocks() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        contract.sync();
+        assert!(contract.is_locked());
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed, PromiseResult::Failed]);
+        contract.post_sync();
+        assert!(!contract.is_locked());
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+    }
+
+    #[test]
+    fn test_skim_with_payout_in_flight() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        let (token_out, amount_out) = contract.swap(&accounts(4), &accounts(2), 100, None).unwrap();
+        contract.deliver_token_out(accounts(4), accounts(4), token_out.clone(), amount_out, None);
+        contract.skim(accounts(5));
+
+        // the payout has not left yet, so it is part of the reported balance but is not surplus
+        set_predecessor(&accounts(0), balance_results(&[1_100, 1_000]));
+        contract.post_skim(accounts(5));
+        assert!(get_created_receipts().is_empty());
+
+        // the failed payout is deposited for the trader out of the tokens the contract still holds
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_deliver_token_out(accounts(4), token_out.clone(), U128(amount_out));
+        assert_eq!(contract.deposits.get(&accounts(4), &token_out), amount_out);
+        assert_eq!(contract.get_balance_differences()[1].surplus, U128(0));
+    }
+
+    fn set_predecessor(predecessor: &AccountId, promise_results: Vec<PromiseResult>) {
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(predecessor.clone())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            promise_results,
+        );
+    }
+
+    // calls that send tokens out on behalf of the caller need one yoctoNEAR attached, like ft_transfer
+    fn set_predecessor_with_one_yocto(predecessor: &AccountId) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(predecessor.clone())
+            .attached_deposit(1)
+            .build());
+    }
+
+    // register the account via storage_deposit with enough storage balance for a few deposits and limit orders
+    fn register_storage(contract: &mut Contract, account: &AccountId) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(account.clone())
+            .attached_deposit(env::storage_byte_cost() * 10_000)
+            .build());
+        contract.storage_deposit(None, None);
+    }
+
+    fn unwrap_value(result: PromiseOrValue<U128>) -> U128 {
+        match result {
+            PromiseOrValue::Value(value) => value,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    #[test]
+    fn test_flash_loan_repaid() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(4), vec![]);
+        contract.flash_loan(accounts(2), U128(100), accounts(5), "arbitrage".to_string());
+        assert_eq!(contract.tokens[0].get_balance(), 900);
+        assert!(contract.get_flash_loan().is_some());
+
+        // fee of 9 basis points is rounded up to 1, and the 1 above what is owed gets refunded
+        set_predecessor(&accounts(2), vec![]);
+        let excess = contract.ft_on_transfer(accounts(5), U128(102), "flash_repay".to_string());
+        assert_eq!(unwrap_value(excess), U128(1));
+        assert_eq!(contract.tokens[0].get_balance(), 1_001);
+        assert!(contract.get_flash_loan().is_some());
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Successful(vec![])]);
+        contract.post_flash_loan();
+        assert!(contract.get_flash_loan().is_none());
+    }
+
+    #[test]
+    fn test_flash_loan_defaulted() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(4), vec![]);
+        contract.flash_loan(accounts(2), U128(100), accounts(5), "arbitrage".to_string());
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(5), U128(60), "flash_repay".to_string());
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_flash_loan();
+        let flash_loan = contract.get_flash_loan().unwrap();
+        assert!(flash_loan.defaulted);
+        assert_eq!(flash_loan.shortfall, U128(41));
+
+        // anyone can settle the debt, which unlocks the contract straight away
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(41), "flash_repay".to_string());
+        assert!(contract.get_flash_loan().is_none());
+        assert_eq!(contract.tokens[0].get_balance(), 1_001);
+    }
+
+    #[test]
+    fn test_flash_loan_transfer_failed() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(4), vec![]);
+        contract.flash_loan(accounts(2), U128(100), accounts(5), "arbitrage".to_string());
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_flash_loan_transfer("arbitrage".to_string());
+        assert!(contract.get_flash_loan().is_none());
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_illegal_swap_while_locked() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(4), vec![]);
+        contract.flash_loan(accounts(2), U128(100), accounts(5), "arbitrage".to_string());
+
+        contract.swap(&accounts(4), &accounts(3), 100, None).unwrap();
+    }
+
+    #[test]
+    fn test_parse_receiver_message() {
+        assert_eq!(
+            "swap".parse::<FungibleTokenReceiverMessage>().unwrap(),
+            FungibleTokenReceiverMessage::Swap {
+                receiver_id: None,
+                receiver_msg: None,
+                referral_id: None,
+                min_amount_out: None,
+            }
+        );
+        assert_eq!(
+            r#"{"swap":{"receiver_id":"bob","receiver_msg":"stake","min_amount_out":"90"}}"#
+                .parse::<FungibleTokenReceiverMessage>()
+                .unwrap(),
+            FungibleTokenReceiverMessage::Swap {
+                receiver_id: Some(accounts(1)),
+                receiver_msg: Some("stake".to_string()),
+                referral_id: None,
+                min_amount_out: Some(U128(90)),
+            }
+        );
+        assert_eq!(
+            "lp_deposit".parse::<FungibleTokenReceiverMessage>().unwrap(),
+            FungibleTokenReceiverMessage::LPDeposit
+        );
+        assert_eq!(
+            r#"{"limit_order":{"min_amount_out":"100"}}"#
+                .parse::<FungibleTokenReceiverMessage>()
+                .unwrap(),
+            FungibleTokenReceiverMessage::LimitOrder { min_amount_out: U128(100) }
+        );
+        assert!("limit_order".parse::<FungibleTokenReceiverMessage>().is_err());
+        assert!("withdraw".parse::<FungibleTokenReceiverMessage>().is_err());
+    }
+
+    fn limit_order_msg(min_amount_out: Balance) -> String {
+        format!(r#"{{"limit_order":{{"min_amount_out":"{}"}}}}"#, min_amount_out)
+    }
+
+    #[test]
+    fn test_limit_order_filled_by_swap() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(100));
+
+        // 100 of token A only gets 90 of token B at the current price, so the order rests
+        let order = contract.get_order(U64(0)).unwrap();
+        assert_eq!(order.min_amount_out, U128(100));
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+        assert_eq!(contract.get_balance_differences()[0].surplus, U128(0));
+
+        // buying token A raises its price until the order gets 128 of token B
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(5), U128(200), "swap".to_string());
+        assert!(contract.get_order(U64(0)).is_none());
+        assert_eq!(contract.order_book.get_escrowed(&accounts(2)), 0);
+        assert_eq!(contract.tokens[0].get_balance(), 934);
+        assert_eq!(contract.tokens[1].get_balance(), 1_072);
+    }
+
+    #[test]
+    fn test_limit_order_filled_when_placed() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(90));
+
+        assert!(contract.get_order(U64(0)).is_none());
+        assert_eq!(contract.tokens[0].get_balance(), 1_100);
+        assert_eq!(contract.tokens[1].get_balance(), 910);
+    }
+
+    #[test]
+    fn test_execute_and_cancel_orders() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        let available = contract.storage_balance_of(accounts(4)).unwrap().available.0;
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(100));
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(1_000));
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(500));
+        let order_storage_cost = Balance::from(ORDER_RECORD_STORAGE_BYTES) * env::storage_byte_cost();
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available.0, available - order_storage_cost * 3);
+
+        let orders = contract.get_orders_by_account(accounts(4), Some(U64(1)), Some(U64(1)));
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].min_amount_out, U128(1_000));
+
+        // the price moved without a swap, e.g. after a sync, so only a keeper can fill the order
+        contract.tokens[1].set_balance(2_000);
+        set_predecessor(&accounts(5), vec![]);
+        assert_eq!(contract.execute_orders(vec![U64(0), U64(1)]), vec![U64(0)]);
+        assert!(contract.get_order(U64(0)).is_none());
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.cancel_order(U64(1));
+        assert_eq!(contract.get_orders_by_account(accounts(4), None, None).len(), 1);
+        // the filled and the cancelled orders gave their storage back, while the fill counted the owner as a trader
+        let trader_storage_cost = Balance::from(TRADER_RECORD_STORAGE_BYTES) * env::storage_byte_cost();
+        let available = available - order_storage_cost - trader_storage_cost;
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available.0, available);
+        assert_eq!(contract.order_book.get_escrowed(&accounts(2)), 100);
+        assert_eq!(contract.tokens[0].get_balance(), 1_100);
+    }
+
+    #[test]
+    #[should_panic(expected = "E701: ")]
+    fn test_limit_order_without_storage() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(100));
+    }
+
+    #[test]
+    fn test_cancel_order_refund_failed() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(1_000));
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.cancel_order(U64(0));
+        assert_eq!(contract.order_book.get_escrowed(&accounts(2)), 0);
+
+        // the refund is kept for the owner instead of being lost to the surplus
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_deliver_token_out(accounts(4), accounts(2), U128(100));
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(100));
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_cancel_order_without_one_yocto() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(1_000));
+
+        set_predecessor(&accounts(4), vec![]);
+        contract.cancel_order(U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E106: ")]
+    fn test_illegal_cancel_order() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(100));
+
+        set_predecessor_with_one_yocto(&accounts(5));
+        contract.cancel_order(U64(0));
+    }
+
+    #[test]
+    fn test_batch_swap() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300), "deposit".to_string());
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(300));
+        assert_eq!(contract.get_balance_differences()[0].surplus, U128(0));
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        let results = contract.batch_swap(vec![
+            batch_swap::SwapAction {
+                token_in: accounts(2),
+                amount_in: U128(100),
+                min_amount_out: U128(90),
+                receiver_id: Some(accounts(5)),
+                receiver_msg: None,
+            },
+            batch_swap::SwapAction {
+                token_in: accounts(2),
+                amount_in: U128(100),
+                min_amount_out: U128(0),
+                receiver_id: None,
+                receiver_msg: None,
+            },
+        ]);
+        // the second swap gets a worse price than the first one
+        assert_eq!(results[0].amount_out, U128(90));
+        assert_eq!(results[0].receiver_id, accounts(5));
+        assert_eq!(results[1].amount_out, U128(75));
+        assert_eq!(results[1].receiver_id, accounts(4));
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(100));
+        assert_eq!(contract.tokens[0].get_balance(), 1_200);
+    }
+
+    #[test]
+    #[should_panic(expected = "E513: ")]
+    fn test_illegal_batch_swap_below_minimum() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300), "deposit".to_string());
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.batch_swap(vec![batch_swap::SwapAction {
+            token_in: accounts(2),
+            amount_in: U128(100),
+            min_amount_out: U128(91),
+            receiver_id: None,
+            receiver_msg: None,
+        }]);
+    }
+
+    #[test]
+    fn test_withdraw_failed() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300), "deposit".to_string());
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.withdraw(accounts(2), None);
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(0));
+
+        let available = contract.storage_balance_of(accounts(4)).unwrap().available;
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_withdraw(accounts(4), accounts(2), U128(300));
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(300));
+        // the deposit is stored at the account's expense again
+        assert!(contract.storage_balance_of(accounts(4)).unwrap().available.0 < available.0);
+    }
+
+    #[test]
+    fn test_deposit_storage_charged() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        register_storage(&mut contract, &accounts(4));
+        let available = contract.storage_balance_of(accounts(4)).unwrap().available;
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300), "deposit".to_string());
+        let charged = U128(available.0 - env::storage_byte_cost() * u128::from(DEPOSIT_RECORD_STORAGE_BYTES));
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available, charged);
+
+        // topping up an existing deposit takes no more storage
+        contract.ft_on_transfer(accounts(4), U128(100), "deposit".to_string());
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available, charged);
+
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.withdraw(accounts(2), None);
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available, available);
+    }
+
+    #[test]
+    #[should_panic(expected = "E701: ")]
+    fn test_illegal_deposit_unregistered() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300), "deposit".to_string());
+    }
+
+    #[test]
+    fn test_undelivered_deposited_unregistered() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_deliver_token_out(accounts(4), accounts(3), U128(50));
+        // owed tokens are deposited even when the account cannot pay for their storage
+        assert_eq!(contract.deposits.get(&accounts(4), &accounts(3)), 50);
+        assert!(contract.storage_balance_of(accounts(4)).is_none());
+    }
+
+    #[test]
+    fn test_swap_to_receiver() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(
+            accounts(4),
+            U128(100),
+            r#"{"swap":{"receiver_id":"fargo","receiver_msg":"stake"}}"#.to_string(),
+        );
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(3));
+        assert!(format!("{:?}", receipts[0].actions).contains("ft_transfer_call"));
+    }
+
+    #[test]
+    fn test_undelivered_swap_deposited() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+
+        // the receiver only used 40 of 90, so the refunded 50 can be withdrawn by the sender
+        set_predecessor(&accounts(0), vec![PromiseResult::Successful(b"\"40\"".to_vec())]);
+        contract.post_deliver_token_out(accounts(4), accounts(3), U128(90));
+        assert_eq!(contract.get_deposits(accounts(4))[1].amount, U128(50));
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_deliver_token_out(accounts(4), accounts(3), U128(90));
+        assert_eq!(contract.get_deposits(accounts(4))[1].amount, U128(140));
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Successful(vec![])]);
+        contract.post_deliver_token_out(accounts(4), accounts(3), U128(90));
+        assert_eq!(contract.get_deposits(accounts(4))[1].amount, U128(140));
+    }
+
+    #[test]
+    fn test_swap_fee_and_referral() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        contract.set_swap_fee(30);
+        contract.set_referral(accounts(5), 5_000);
+        // 1_000 pays a fee of 3, so the swap prices 997
+        assert_eq!(contract.get_amount_out(accounts(2), U128(1_000)), U128(906));
+        assert_eq!(contract.get_amount_in(accounts(3), U128(906)), U128(1_000));
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(
+            accounts(4),
+            U128(1_000),
+            r#"{"swap":{"referral_id":"fargo"}}"#.to_string(),
+        );
+        // the referrer gets half of the fee rounded down, the rest stays in the pool
+        assert_eq!(contract.tokens[0].get_balance(), 10_999);
+        assert_eq!(contract.tokens[1].get_balance(), 9_094);
+        assert_eq!(contract.get_referral_earnings(accounts(5))[0].amount, U128(1));
+        assert_eq!(contract.get_deposits(accounts(5))[0].amount, U128(1));
+        assert_eq!(contract.get_referrals(None, None)[0].fee_share_bps, 5_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E515: ")]
+    fn test_illegal_swap_with_unknown_referral() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(
+            accounts(4),
+            U128(1_000),
+            r#"{"swap":{"referral_id":"fargo"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_dynamic_fee_rises_with_price_movement() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        contract.set_swap_fee(30);
+        contract.set_dynamic_fee(5, 100, 10);
+        assert_eq!(contract.get_swap_fee(), 5);
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        // the price of token A fell by 17.3%, which takes the fee to its ceiling
+        let dynamic_fee = contract.get_dynamic_fee().unwrap();
+        assert_eq!(dynamic_fee.volatility_bps, U64(1_734));
+        assert_eq!(dynamic_fee.fee_bps, 100);
+        assert_eq!(contract.get_swap_fee(), 100);
+
+        set_predecessor(&accounts(1), vec![]);
+        contract.remove_dynamic_fee();
+        assert_eq!(contract.get_swap_fee(), 30);
+    }
+
+    #[test]
+    fn test_swap_limits_refund_and_pause() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        contract.set_swap_limits(1_000, 5_000, 3_000, U64(100));
+
+        // 2_000 of token A would take 1_666 of token B, above 10% of its reserve
+        set_predecessor(&accounts(2), vec![]);
+        let refund = contract.ft_on_transfer(accounts(4), U128(2_000), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(2_000));
+        assert_eq!(contract.tokens[0].get_balance(), 10_000);
+
+        // two swaps moving the price about 18% each trip the breaker, which refunds every later swap
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        assert!(contract.get_swap_limits().unwrap().tripped);
+        set_predecessor(&accounts(2), vec![]);
+        let refund = contract.ft_on_transfer(accounts(4), U128(10), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(10));
+
+        set_predecessor(&accounts(1), vec![]);
+        contract.reset_circuit_breaker();
+        set_predecessor(&accounts(2), vec![]);
+        let refund = contract.ft_on_transfer(accounts(4), U128(10), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(0));
+    }
+
+    #[test]
+    fn test_access_list_refunds_swaps() {
+        let mut contract = active_contract((8, 6), (1_000, 1_000));
+        contract.add_to_allowlist(vec![accounts(4)]);
+        contract.set_access_mode(access_list::AccessMode::Allowlist);
+        assert_eq!(contract.get_allowlist(None, None), vec![accounts(4)]);
+
+        set_predecessor(&accounts(2), vec![]);
+        let refund = contract.ft_on_transfer(accounts(5), U128(100), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(100));
+        let refund = contract.ft_on_transfer(accounts(4), U128(100), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(0));
+
+        set_predecessor(&accounts(1), vec![]);
+        contract.add_to_denylist(vec![accounts(4), accounts(5)]);
+        contract.remove_from_denylist(vec![accounts(5)]);
+        contract.set_access_mode(access_list::AccessMode::Denylist);
+        set_predecessor(&accounts(2), vec![]);
+        let refund = contract.ft_on_transfer(accounts(4), U128(100), limit_order_msg(200));
+        assert_eq!(unwrap_value(refund), U128(100));
+        let refund = contract.ft_on_transfer(accounts(5), U128(100), "swap".to_string());
+        assert_eq!(unwrap_value(refund), U128(0));
+    }
+
+    #[test]
+    fn test_swap_near() {
+        let mut contract = active_contract((24, 6), (1_000, 1_000));
+        contract.set_wrap_near(accounts(2));
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(100)
+            .build());
+        contract.swap_near();
+        assert_eq!(get_created_receipts()[0].receiver_id, accounts(2));
+
+        set_predecessor(&accounts(0), vec![PromiseResult::Successful(vec![])]);
+        contract.post_wrap_near(accounts(4), U128(100));
+        assert_eq!(contract.tokens[0].get_balance(), 1_100);
+        assert_eq!(contract.tokens[1].get_balance(), 910);
+
+        // the attached NEAR is sent back when it could not be wrapped
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_wrap_near(accounts(4), U128(100));
+        let receipts = get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(4));
+        assert!(format!("{:?}", receipts[0].actions).contains("Transfer"));
+    }
+
+    #[test]
+    fn test_swap_near_dust_deposited() {
+        let mut contract = active_contract((24, 6), (1_000, 1_000));
+        contract.set_wrap_near(accounts(2));
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(1)
+            .build());
+        contract.swap_near();
+
+        // too little to get anything out, so the wNEAR is deposited instead of failing the callback
+        set_predecessor(&accounts(0), vec![PromiseResult::Successful(vec![])]);
+        contract.post_wrap_near(accounts(4), U128(1));
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(1));
+        assert_eq!(contract.tokens[0].get_balance(), 1_000);
+        assert_eq!(contract.transfers_in_flight.get(&accounts(2)), 0);
+    }
+
+    #[test]
+    fn test_swap_to_near() {
+        let mut contract = active_contract((24, 6), (1_000, 1_000));
+        contract.set_wrap_near(accounts(2));
+
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), "swap_to_near".to_string());
+        assert_eq!(contract.tokens[0].get_balance(), 910);
+        let receipts = get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(2));
+        assert!(format!("{:?}", receipts[0].actions).contains("near_withdraw"));
+
+        // wNEAR that could not be unwrapped can be withdrawn instead
+        set_predecessor(&accounts(0), vec![PromiseResult::Failed]);
+        contract.post_unwrap_near(accounts(4), U128(90));
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(90));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        contract.set_swap_fee(30);
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(5), U128(1_000), "swap".to_string());
+        assert!(get_logs().iter().any(|log| log.starts_with("Swap by fargo of 1000 token danny")));
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.swap_count, U128(3));
+        assert_eq!(stats.unique_trader_count, U128(2));
+        assert_eq!(stats.tokens[0].liquidity_added, "10000");
+        assert_eq!(stats.tokens[0].fees, "6");
+        assert_eq!(stats.tokens[1].fees, "3");
+        assert!(stats.last_swap_block_height.is_some());
+    }
+
+    #[test]
+    fn test_trader_storage_charged() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        register_storage(&mut contract, &accounts(4));
+        let available = contract.storage_balance_of(accounts(4)).unwrap().available.0;
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        contract.ft_on_transfer(accounts(4), U128(1_000), "swap".to_string());
+        let trader_storage_cost = Balance::from(TRADER_RECORD_STORAGE_BYTES) * env::storage_byte_cost();
+        assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available.0, available - trader_storage_cost);
+        assert_eq!(contract.get_stats().unique_trader_count, U128(1));
+
+        // the record counting the trader is kept for good, so it does not stop the account from unregistering
+        set_predecessor_with_one_yocto(&accounts(4));
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(4)).is_none());
+        assert_eq!(contract.get_stats().unique_trader_count, U128(1));
+    }
+
+    #[test]
+    fn test_swap_history() {
+        let mut contract = active_contract((8, 6), (10_000, 10_000));
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(near_sdk::ONE_NEAR)
+            .build());
+        contract.set_swap_history_size(2);
+
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(100), "swap".to_string());
+        contract.ft_on_transfer(accounts(5), U128(200), "swap".to_string());
+        contract.ft_on_transfer(accounts(4), U128(300), "swap".to_string());
+
+        let swaps = contract.get_swaps(None, None);
+        assert_eq!(swaps.len(), 2);
+        assert_eq!(swaps[0].amount_in, U128(300));
+        assert_eq!(swaps[1].sender_id, accounts(5));
+        assert_eq!(contract.get_swaps(Some(U64(1)), Some(U64(1))), swaps[1..]);
+
+        let swaps = contract.get_swaps_by_account(accounts(4), None);
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].token_out, accounts(3));
+    }
+
+    #[test]
+    fn test_rewards_claimed() {
+        let mut contract = active_contract((8, 6), (10_000, 100));
+        assert_eq!(contract.get_shares(accounts(1)), U128(1_000));
+
+        // the reward token does not need to be a pool token
+        set_predecessor(&accounts(5), vec![]);
+        let msg = r#"{"fund_rewards":{"start":"0","end":"100","rate":"10"}}"#.to_string();
+        assert_eq!(unwrap_value(contract.ft_on_transfer(accounts(1), U128(1_500), msg)), U128(500));
+
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(40_000_000_000)
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.get_unclaimed_rewards(accounts(1)), U128(400));
+        contract.claim_rewards();
+        assert_eq!(contract.get_unclaimed_rewards(accounts(1)), U128(0));
+        assert_eq!(contract.rewards.get_held(&accounts(5)), 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "E601: ")]
+    fn test_illegal_lp_deposit_into_active_pool() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "lp_deposit".to_string());
+    }
+
+    #[test]
+    fn test_add_liquidity() {
+        let mut contract = active_contract((8, 6), (1_000_000, 2_000_000));
+        register_storage(&mut contract, &accounts(4));
+        set_predecessor(&accounts(2), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(300_000), "deposit".to_string());
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(400_000), "deposit".to_string());
+
+        // the token 3 deposit only covers a fifth of the reserves, so the rest of token 2 stays deposited
+        set_predecessor(&accounts(4), vec![]);
+        let total_shares = contract.get_total_shares().0;
+        let shares = contract.add_liquidity([U128(300_000), U128(400_000)], U128(1));
+        assert_eq!(shares.0, total_shares / 5);
+        assert_eq!(contract.get_shares(accounts(4)), shares);
+        assert_eq!(contract.tokens[0].get_balance(), 1_200_000);
+        assert_eq!(contract.tokens[1].get_balance(), 2_400_000);
+        assert_eq!(contract.get_deposits(accounts(4))[0].amount, U128(100_000));
+        assert_eq!(contract.get_deposits(accounts(4))[1].amount, U128(0));
+    }
+
+    #[test]
+    fn test_add_liquidity_round_trip_pays_the_swap_fee() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        contract.set_swap_fee(30);
+        register_storage(&mut contract, &accounts(4));
+        for token in [accounts(2), accounts(3)] {
+            set_predecessor(&token, vec![]);
+            contract.ft_on_transfer(accounts(4), U128(100_000), "deposit".to_string());
+        }
+        let amount_out = contract.get_amount_out(accounts(3), U128(100_000)).0;
+
+        // adding both tokens and leaving in one of them never beats swapping one into the other
+        set_predecessor(&accounts(4), vec![]);
+        let shares = contract.add_liquidity([U128(100_000), U128(100_000)], U128(1));
+        set_predecessor_with_one_yocto(&accounts(4));
+        contract.remove_liquidity_one_token(shares, accounts(2), U128(0));
+        assert!(contract.transfers_in_flight.get(&accounts(2)) <= 100_000 + amount_out);
+    }
+
+    #[test]
+    #[should_panic(expected = "E601: ")]
+    fn test_illegal_lp_deposit_before_active() {
+        let mut contract = active_contract((8, 6), (1_000_000, 0));
+        set_predecessor(&accounts(3), vec![]);
+        contract.ft_on_transfer(accounts(4), U128(1_000_000), "lp_deposit".to_string());
+    }
+
+    #[test]
+    fn test_zap_in() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        contract.set_swap_fee(30);
+        let total_shares = contract.get_total_shares().0;
+
+        set_predecessor(&accounts(2), vec![]);
+        let msg = r#"{"zap_in":{"min_shares":"1"}}"#.to_string();
+        assert_eq!(unwrap_value(contract.ft_on_transfer(accounts(4), U128(1_000_000), msg)), U128(0));
+
+        // the whole amount ends up in the pool, and the price is that of the swap
+        assert_eq!(contract.tokens[0].get_balance(), 2_000_000);
+        assert_eq!(contract.tokens[1].get_balance(), 1_000_000);
+        let shares = contract.get_shares(accounts(4)).0;
+        assert!(shares > 0 && shares < total_shares);
+        assert_eq!(contract.get_total_shares().0, total_shares + shares);
+    }
+
+    #[test]
+    #[should_panic(expected = "E526: ")]
+    fn test_zap_in_below_min_shares() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor(&accounts(2), vec![]);
+        let msg = r#"{"zap_in":{"min_shares":"1000000"}}"#.to_string();
+        contract.ft_on_transfer(accounts(4), U128(1_000_000), msg);
+    }
+
+    #[test]
+    fn test_remove_liquidity_one_token() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        assert_eq!(contract.get_shares(accounts(1)), U128(1_000_000));
+
+        // a tenth of both reserves, of which the part in token 3 is swapped into token 2 against the rest
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity_one_token(U128(100_000), accounts(2), U128(190_000));
+        assert_eq!(contract.get_shares(accounts(1)), U128(900_000));
+        assert_eq!(contract.tokens[0].get_balance(), 810_000);
+        assert_eq!(contract.tokens[1].get_balance(), 1_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E513: ")]
+    fn test_remove_liquidity_one_token_below_minimum() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity_one_token(U128(100_000), accounts(2), U128(190_001));
+    }
+
+    #[test]
+    #[should_panic(expected = "E528: ")]
+    fn test_illegal_remove_liquidity_one_token_last_lp() {
+        let mut contract = active_contract((8, 6), (1_000_000, 2_000_000));
+        let total_shares = contract.get_total_shares();
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity_one_token(total_shares, accounts(2), U128(1_000_000));
+    }
+
+    #[test]
+    fn test_remove_liquidity() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity(U128(100_000), [U128(100_000), U128(100_000)]);
+        assert!(contract.tokens.iter().all(|token| token.get_balance() == 900_000));
+        assert_eq!(contract.transfers_in_flight.get(&accounts(2)), 100_000);
+        assert_eq!(contract.transfers_in_flight.get(&accounts(3)), 100_000);
+    }
+
+    #[test]
+    fn test_remove_liquidity_last_lp() {
+        let mut contract = active_contract((8, 6), (1_000_000, 2_000_000));
+        let total_shares = contract.get_total_shares();
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity(total_shares, [U128(1_000_000), U128(2_000_000)]);
+
+        // both reserves are sent, and the pool awaits liquidity again
+        assert_eq!(contract.get_total_shares(), U128(0));
+        assert!(contract.tokens.iter().all(|token| token.get_balance() == 0));
+        assert_eq!(contract.pool_state, PoolState::AwaitingLiquidity);
+        assert_eq!(contract.transfers_in_flight.get(&accounts(2)), 1_000_000);
+        assert_eq!(contract.transfers_in_flight.get(&accounts(3)), 2_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E513: ")]
+    fn test_remove_liquidity_below_minimum() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor_with_one_yocto(&accounts(1));
+        contract.remove_liquidity(U128(100_000), [U128(100_000), U128(100_001)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_illegal_remove_liquidity_one_token_without_one_yocto() {
+        let mut contract = active_contract((8, 6), (1_000_000, 1_000_000));
+        set_predecessor(&accounts(1), vec![]);
+        contract.remove_liquidity_one_token(U128(100_000), accounts(2), U128(0));
+    }
 }
@@ -0,0 +1,33 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    serde::{Deserialize, Serialize},
+    store::Vector,
+};
+
+use crate::token::Token;
+
+// lifecycle of the pool, which moves forward as the token metadata and liquidity become available, and back to
+// AwaitingLiquidity when a sync finds that a reserve has been emptied
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PoolState {
+    // metadata cross-contract calls fired in `new` have not all called back yet, so decimals are unknown
+    AwaitingMetadata,
+    // decimals are known, so liquidity can be provided, but not both tokens have been provided yet
+    AwaitingLiquidity,
+    // liquidity of both tokens is provided, so swap is supported
+    Active,
+}
+
+impl PoolState {
+    // derive the state from the tokens, as every transition is caused by a change in the tokens
+    pub fn from_tokens(tokens: &Vector<Token>) -> Self {
+        if !tokens.iter().all(|token| token.has_metadata()) {
+            Self::AwaitingMetadata
+        } else if !tokens.iter().all(|token| token.get_balance() > 0) {
+            Self::AwaitingLiquidity
+        } else {
+            Self::Active
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use near_amm_math::{div_round, quote_amount_out, Rounding, U256};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, TreeMap},
+    env,
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance, Promise,
+};
+use std::ops::Bound;
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::util::{MAX_OPEN_ORDERS_PER_ACCOUNT, MAX_ORDERS_CHECKED_PER_SWAP, ORDER_RECORD_STORAGE_BYTES};
+use crate::{Contract, ContractExt, StorageKey};
+
+// precision of the limit price used to sort orders, the exact limit is checked when filling
+const PRICE_KEY_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LimitOrderMetadata {
+    id: U64,
+    owner: AccountId,
+    token_in: AccountId,
+    amount_in: U128,
+    pub min_amount_out: U128,
+}
+
+// resting order to sell all of amount_in for at least min_amount_out of the other token,
+// i.e. when the price of token_in is at least min_amount_out / amount_in
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LimitOrder {
+    id: u64,
+    owner: AccountId,
+    token_in: AccountId,
+    // escrowed by the contract, outside of the pool's liquidity
+    amount_in: Balance,
+    min_amount_out: Balance,
+}
+
+impl LimitOrder {
+    pub fn get_metadata(&self) -> LimitOrderMetadata {
+        LimitOrderMetadata {
+            id: U64(self.id),
+            owner: self.owner.clone(),
+            token_in: self.token_in.clone(),
+            amount_in: U128(self.amount_in),
+            min_amount_out: U128(self.min_amount_out),
+        }
+    }
+
+    // limit price scaled to an integer so that orders can be sorted by it, saturating for absurd limits
+    fn get_price_key(&self) -> u128 {
+        let price = div_round(
+            U256::from(self.min_amount_out) * U256::from(PRICE_KEY_PRECISION),
+            U256::from(self.amount_in),
+            Rounding::Up,
+        );
+
+        if price > U256::from(u128::MAX) {
+            u128::MAX
+        } else {
+            price.as_u128()
+        }
+    }
+
+    // whether selling the whole order against the given reserves and fee would meet its limit
+    pub fn is_crossed(&self, balance_in: Balance, balance_out: Balance, fee_bps: u16) -> bool {
+        let amount_out = quote_amount_out(self.amount_in, balance_in, balance_out, fee_bps).unwrap_or_panic();
+        amount_out > 0 && amount_out >= self.min_amount_out
+    }
+}
+
+// every open limit order, indexed by price for filling and by owner for listing
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OrderBook {
+    next_order_id: u64,
+    orders: LookupMap<u64, LimitOrder>,
+    // sorted by the token sold and then by limit price, so the cheapest orders get filled first
+    orders_by_price: TreeMap<(AccountId, u128, u64), ()>,
+    orders_by_owner: TreeMap<(AccountId, u64), ()>,
+    // total escrowed per token, which is held by the contract but is not liquidity
+    escrowed: LookupMap<AccountId, Balance>,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self {
+            next_order_id: 0,
+            orders: LookupMap::new(StorageKey::Orders),
+            orders_by_price: TreeMap::new(StorageKey::OrdersByPrice),
+            orders_by_owner: TreeMap::new(StorageKey::OrdersByOwner),
+            escrowed: LookupMap::new(StorageKey::OrderEscrows),
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<LimitOrder> {
+        self.orders.get(&id)
+    }
+
+    pub fn get_escrowed(&self, token: &AccountId) -> Balance {
+        self.escrowed.get(token).unwrap_or(0)
+    }
+
+    pub fn count_by_owner(&self, owner: &AccountId) -> usize {
+        self.ids_by_owner(owner).count()
+    }
+
+    pub fn ids_by_owner<'a>(&'a self, owner: &AccountId) -> impl Iterator<Item = u64> + 'a {
+        self.orders_by_owner
+            .range((
+                Bound::Included((owner.clone(), 0)),
+                Bound::Included((owner.clone(), u64::MAX)),
+            ))
+            .map(|((_, id), _)| id)
+    }
+
+    // ids of the orders selling the given token, cheapest limit first
+    pub fn ids_by_price<'a>(&'a self, token_in: &AccountId) -> impl Iterator<Item = u64> + 'a {
+        self.orders_by_price
+            .range((
+                Bound::Included((token_in.clone(), 0, 0)),
+                Bound::Included((token_in.clone(), u128::MAX, u64::MAX)),
+            ))
+            .map(|((_, _, id), _)| id)
+    }
+
+    pub fn insert(&mut self, owner: AccountId, token_in: AccountId, amount_in: Balance, min_amount_out: Balance) -> u64 {
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+
+        let order = LimitOrder {
+            id,
+            owner,
+            token_in,
+            amount_in,
+            min_amount_out,
+        };
+        self.orders_by_price
+            .insert(&(order.token_in.clone(), order.get_price_key(), id), &());
+        self.orders_by_owner.insert(&(order.owner.clone(), id), &());
+        self.escrowed.insert(
+            &order.token_in,
+            &self
+                .get_escrowed(&order.token_in)
+                .checked_add(amount_in)
+                .or_panic(AmmError::InternalOverflowError),
+        );
+        self.orders.insert(&id, &order);
+
+        id
+    }
+
+    // remove the order and release its escrow, which the caller then either swaps or refunds
+    pub fn remove(&mut self, id: u64) -> LimitOrder {
+        let order = self.orders.remove(&id).or_panic(AmmError::LimitOrderNotFound);
+        self.orders_by_price
+            .remove(&(order.token_in.clone(), order.get_price_key(), id));
+        self.orders_by_owner.remove(&(order.owner.clone(), id));
+        self.escrowed.insert(
+            &order.token_in,
+            &self
+                .get_escrowed(&order.token_in)
+                .checked_sub(order.amount_in)
+                .or_panic(AmmError::InternalOverflowError),
+        );
+
+        order
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get an open limit order
+    pub fn get_order(&self, order_id: U64) -> Option<LimitOrderMetadata> {
+        self.order_book.get(order_id.0).map(|order| order.get_metadata())
+    }
+
+    // public method to list the open limit orders of an account, in the order they were placed
+    pub fn get_orders_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<LimitOrderMetadata> {
+        self.order_book
+            .ids_by_owner(&account_id)
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.map_or(MAX_OPEN_ORDERS_PER_ACCOUNT, |limit| limit.0 as usize))
+            .map(|id| {
+                self.order_book
+                    .get(id)
+                    .or_panic(AmmError::LimitOrderNotFound)
+                    .get_metadata()
+            })
+            .collect()
+    }
+
+    // cancel an open limit order, only its owner can cancel, and refund the escrowed tokens
+    // a refund that cannot be delivered is deposited for the owner to withdraw
+    #[payable]
+    pub fn cancel_order(&mut self, order_id: U64) -> Promise {
+        assert_one_yocto();
+        let order = self.order_book.get(order_id.0).or_panic(AmmError::LimitOrderNotFound);
+        ensure(env::predecessor_account_id() == order.owner, AmmError::CallerNotOrderOwner);
+
+        let order = self.remove_order(order_id.0);
+        log!("Limit order {} cancelled, refunding {} of token {}!", order.id, order.amount_in, order.token_in);
+        self.deliver_token_out(order.owner.clone(), order.owner, order.token_in, order.amount_in, None)
+    }
+
+    // fill the given limit orders whose limit is met by the current price, callable by anyone, e.g. keepers
+    // returns the ids of the orders that got filled
+    pub fn execute_orders(&mut self, order_ids: Vec<U64>) -> Vec<U64> {
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        self.assert_unlocked();
+
+        order_ids
+            .into_iter()
+            .filter(|order_id| self.fill_order(order_id.0))
+            .collect()
+    }
+
+    // escrow the transferred tokens as a limit order, which is filled straight away if its limit is already met
+    // the storage of the order is charged to the storage balance of its owner until it is cancelled or filled
+    pub(crate) fn place_order(&mut self, owner: AccountId, token_in: AccountId, amount_in: Balance, min_amount_out: Balance) {
+        ensure(min_amount_out > 0, AmmError::InvalidLimitOrder);
+        ensure(
+            self.order_book.count_by_owner(&owner) < MAX_OPEN_ORDERS_PER_ACCOUNT,
+            AmmError::TooManyLimitOrders,
+        );
+        self.storage_accounts
+            .charge(&owner, ORDER_RECORD_STORAGE_BYTES)
+            .unwrap_or_panic();
+
+        let id = self.order_book.insert(owner.clone(), token_in.clone(), amount_in, min_amount_out);
+        log!(
+            "Limit order {} placed by {} to sell {} of token {} for at least {}!",
+            id,
+            owner,
+            amount_in,
+            token_in,
+            min_amount_out
+        );
+
+        if self.pool_state == PoolState::Active && !self.is_locked() {
+            self.fill_order(id);
+        }
+    }
+
+    // after a swap raised the price of token_in, fill the cheapest orders selling it whose limit is now met
+    pub(crate) fn fill_crossed_orders(&mut self, token_in: &AccountId) {
+        let order_ids: Vec<u64> = self
+            .order_book
+            .ids_by_price(token_in)
+            .take(MAX_ORDERS_CHECKED_PER_SWAP)
+            .collect();
+
+        order_ids.into_iter().for_each(|id| {
+            self.fill_order(id);
+        });
+    }
+
+    // swap the escrow of the order against the pool and send the output to its owner, if its limit is met
+    // an output that cannot be delivered is deposited for the owner to withdraw
+    fn fill_order(&mut self, id: u64) -> bool {
+        let is_crossed = match self.order_book.get(id) {
+            Some(order) => {
+                let (token_in, token_out) = self.get_token_pair(&order.token_in);
+                order.is_crossed(token_in.get_balance(), token_out.get_balance(), self.get_swap_fee())
+            }
+            None => false,
+        };
+        if !is_crossed {
+            return false;
+        }
+
+        // an order whose fill would break the swap limits stays open
+        let order = self.order_book.get(id).or_panic(AmmError::LimitOrderNotFound);
+        let (token_out, amount_out) = match self.swap(&order.owner, &order.token_in, order.amount_in, None) {
+            Ok(swapped) => swapped,
+            Err(error) => {
+                log!("Limit order {} not filled: {}", id, error);
+                return false;
+            }
+        };
+        self.remove_order(id);
+        log!("Limit order {} filled for {} of token {}!", id, amount_out, token_out);
+        self.deliver_token_out(order.owner.clone(), order.owner, token_out, amount_out, None);
+
+        true
+    }
+
+    // remove the order and give its storage back to its owner
+    fn remove_order(&mut self, id: u64) -> LimitOrder {
+        let order = self.order_book.remove(id);
+        self.storage_accounts.release(&order.owner, ORDER_RECORD_STORAGE_BYTES);
+
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_order_book() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut order_book = OrderBook::new();
+        let expensive = order_book.insert(accounts(0), accounts(2), 100, 300);
+        let cheap = order_book.insert(accounts(1), accounts(2), 100, 150);
+        let other = order_book.insert(accounts(0), accounts(3), 50, 10);
+
+        assert_eq!(order_book.ids_by_price(&accounts(2)).collect::<Vec<u64>>(), vec![cheap, expensive]);
+        assert_eq!(order_book.ids_by_owner(&accounts(0)).collect::<Vec<u64>>(), vec![expensive, other]);
+        assert_eq!(order_book.get_escrowed(&accounts(2)), 200);
+
+        order_book.remove(cheap);
+        assert_eq!(order_book.ids_by_price(&accounts(2)).collect::<Vec<u64>>(), vec![expensive]);
+        assert_eq!(order_book.get_escrowed(&accounts(2)), 100);
+        assert!(order_book.get(cheap).is_none());
+    }
+
+    #[test]
+    fn test_is_crossed() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut order_book = OrderBook::new();
+        let id = order_book.insert(accounts(0), accounts(2), 100, 90);
+        let order = order_book.get(id).unwrap();
+
+        assert!(order.is_crossed(1_000, 1_000, 0));
+        assert!(!order.is_crossed(1_000, 900, 0));
+        assert!(!order.is_crossed(1_000, 1_000, 500));
+    }
+}
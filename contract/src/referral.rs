@@ -0,0 +1,109 @@
+use near_amm_math::BASIS_POINTS_DENOMINATOR;
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, UnorderedMap},
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance,
+};
+
+use crate::error::*;
+use crate::{Contract, ContractExt, StorageKey};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Referral {
+    referral_id: AccountId,
+    pub fee_share_bps: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralEarning {
+    token_id: AccountId,
+    pub amount: U128,
+}
+
+// referrers whitelisted by the owner, who get a share of the swap fee of the swaps they refer
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Referrals {
+    fee_shares_bps: UnorderedMap<AccountId, u16>,
+    // lifetime earnings keyed by referrer and then token, which are credited to the referrer's deposit as they accrue
+    earnings: LookupMap<(AccountId, AccountId), Balance>,
+}
+
+impl Referrals {
+    pub fn new() -> Self {
+        Self {
+            fee_shares_bps: UnorderedMap::new(StorageKey::ReferralFeeShares),
+            earnings: LookupMap::new(StorageKey::ReferralEarnings),
+        }
+    }
+
+    pub fn get_fee_share(&self, referral_id: &AccountId) -> Option<u16> {
+        self.fee_shares_bps.get(referral_id)
+    }
+
+    pub fn get_earning(&self, referral_id: &AccountId, token: &AccountId) -> Balance {
+        self.earnings
+            .get(&(referral_id.clone(), token.clone()))
+            .unwrap_or(0)
+    }
+
+    pub fn add_earning(&mut self, referral_id: &AccountId, token: &AccountId, amount: Balance) {
+        let earning = self
+            .get_earning(referral_id, token)
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        self.earnings.insert(&(referral_id.clone(), token.clone()), &earning);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to list the whitelisted referrers with their share of the swap fee
+    pub fn get_referrals(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<Referral> {
+        self.referrals
+            .fee_shares_bps
+            .iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.map_or(usize::MAX, |limit| limit.0 as usize))
+            .map(|(referral_id, fee_share_bps)| Referral {
+                referral_id,
+                fee_share_bps,
+            })
+            .collect()
+    }
+
+    // public method to get everything a referrer has earned in every token of the pool, whether withdrawn or not
+    pub fn get_referral_earnings(&self, referral_id: AccountId) -> Vec<ReferralEarning> {
+        self.tokens
+            .iter()
+            .map(|token| ReferralEarning {
+                token_id: token.get_address().clone(),
+                amount: U128(self.referrals.get_earning(&referral_id, token.get_address())),
+            })
+            .collect()
+    }
+
+    // whitelist a referrer or change their share of the swap fee, only contract's owner can set it
+    pub fn set_referral(&mut self, referral_id: AccountId, fee_share_bps: u16) {
+        self.assert_owner();
+        ensure(fee_share_bps <= BASIS_POINTS_DENOMINATOR, AmmError::InvalidFeeBasisPoints);
+
+        log!("Referrer {} gets {} basis points of the swap fee!", referral_id, fee_share_bps);
+        self.referrals.fee_shares_bps.insert(&referral_id, &fee_share_bps);
+    }
+
+    // remove a referrer from the whitelist, only contract's owner can remove, their earnings stay withdrawable
+    pub fn remove_referral(&mut self, referral_id: AccountId) {
+        self.assert_owner();
+        self.referrals
+            .fee_shares_bps
+            .remove(&referral_id)
+            .or_panic(AmmError::ReferralNotWhitelisted);
+
+        log!("Referrer {} removed!", referral_id);
+    }
+}
@@ -0,0 +1,288 @@
+use near_amm_math::U256;
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance, Promise,
+};
+use std::cmp;
+use std::convert::TryFrom;
+
+use crate::error::*;
+use crate::util::REWARD_PER_SHARE_DECIMAL;
+use crate::{Contract, ContractExt, StorageKey};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardScheduleMetadata {
+    token_id: AccountId,
+    start: U64,
+    end: U64,
+    rate: U128,
+}
+
+// reward token paid out at a constant rate between start and end, which are unix timestamps in seconds
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RewardSchedule {
+    token_id: AccountId,
+    start: u64,
+    end: u64,
+    // paid out per second
+    rate: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+struct AccountRewards {
+    // reward per share as of the last change of the account's shares
+    reward_per_share_paid: U256,
+    unclaimed: Balance,
+}
+
+// liquidity mining rewards shared by LPs pro rata to their shares, using the reward per share accumulator
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Rewards {
+    schedule: Option<RewardSchedule>,
+    // rewards paid out per share since the first schedule, scaled up by 10 ^ REWARD_PER_SHARE_DECIMAL
+    reward_per_share: U256,
+    last_update: u64,
+    accounts: LookupMap<AccountId, AccountRewards>,
+    // funded but not claimed yet, which is held by the contract but is not liquidity
+    held: Balance,
+    // part of held paid out while there were no shares, which nobody earned, so the next schedule is funded with it
+    unallocated: Balance,
+}
+
+fn now() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+impl Rewards {
+    pub fn new() -> Self {
+        Self {
+            schedule: None,
+            reward_per_share: U256::zero(),
+            last_update: now(),
+            accounts: LookupMap::new(StorageKey::RewardAccounts),
+            held: 0,
+            unallocated: 0,
+        }
+    }
+
+    pub fn get_held(&self, token: &AccountId) -> Balance {
+        match &self.schedule {
+            Some(schedule) if &schedule.token_id == token => self.held,
+            _ => 0,
+        }
+    }
+
+    // rewards paid out by the schedule since the last update
+    fn get_paid_out(&self) -> U256 {
+        let schedule = match &self.schedule {
+            Some(schedule) => schedule,
+            None => return U256::zero(),
+        };
+        let from = self.last_update.clamp(schedule.start, schedule.end);
+        let to = now().clamp(schedule.start, schedule.end);
+
+        U256::from(schedule.rate) * U256::from(to.saturating_sub(from))
+    }
+
+    // reward per share up to now, rewards of the time without any shares are left unallocated by update
+    fn get_reward_per_share(&self, total_shares: Balance) -> U256 {
+        if total_shares == 0 {
+            return self.reward_per_share;
+        }
+
+        self.reward_per_share + self.get_paid_out() * U256::exp10(REWARD_PER_SHARE_DECIMAL) / U256::from(total_shares)
+    }
+
+    pub fn update(&mut self, total_shares: Balance) {
+        if total_shares == 0 {
+            // never more than the schedule was funded with, so it fits
+            self.unallocated += self.get_paid_out().as_u128();
+        }
+        self.reward_per_share = self.get_reward_per_share(total_shares);
+        self.last_update = now();
+    }
+
+    fn get_unclaimed(&self, account: &AccountId, shares: Balance, reward_per_share: U256) -> Balance {
+        let account_rewards = self.accounts.get(account).unwrap_or_default();
+        let earned = U256::from(shares) * (reward_per_share - account_rewards.reward_per_share_paid)
+            / U256::exp10(REWARD_PER_SHARE_DECIMAL);
+
+        Balance::try_from(earned)
+            .ok()
+            .and_then(|earned| earned.checked_add(account_rewards.unclaimed))
+            .or_panic(AmmError::InternalOverflowError)
+    }
+
+    // add what the shares of the account earned since their last change, must follow update
+    pub fn settle(&mut self, account: &AccountId, shares: Balance) {
+        let unclaimed = self.get_unclaimed(account, shares, self.reward_per_share);
+        self.accounts.insert(
+            account,
+            &AccountRewards {
+                reward_per_share_paid: self.reward_per_share,
+                unclaimed,
+            },
+        );
+    }
+
+    // the reward token stays the same across schedules, and a schedule can only follow the previous one,
+    // which carries its unallocated rewards over, returns the part of the amount above what the schedule pays out
+    pub fn fund(
+        &mut self,
+        total_shares: Balance,
+        token: &AccountId,
+        amount: Balance,
+        start: u64,
+        end: u64,
+        rate: Balance,
+    ) -> Balance {
+        let start = cmp::max(start, now());
+        ensure(start < end && rate > 0, AmmError::InvalidRewardSchedule);
+        if let Some(schedule) = &self.schedule {
+            ensure(&schedule.token_id == token && now() >= schedule.end, AmmError::InvalidRewardSchedule);
+        }
+        self.update(total_shares);
+        let carried = self.unallocated;
+        let available = amount.checked_add(carried).or_panic(AmmError::InternalOverflowError);
+        let required = U256::from(rate) * U256::from(end - start);
+        ensure(required <= U256::from(available), AmmError::InvalidRewardSchedule);
+        let required = required.as_u128();
+
+        self.schedule = Some(RewardSchedule {
+            token_id: token.clone(),
+            start,
+            end,
+            rate,
+        });
+        self.unallocated = 0;
+        self.held = (self.held - carried)
+            .checked_add(required)
+            .or_panic(AmmError::InternalOverflowError);
+
+        available - required
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the current or last reward schedule, none until the owner funds one
+    pub fn get_reward_schedule(&self) -> Option<RewardScheduleMetadata> {
+        self.rewards.schedule.as_ref().map(|schedule| RewardScheduleMetadata {
+            token_id: schedule.token_id.clone(),
+            start: U64(schedule.start),
+            end: U64(schedule.end),
+            rate: U128(schedule.rate),
+        })
+    }
+
+    // public method to get the rewards an account can claim
+    pub fn get_unclaimed_rewards(&self, account_id: AccountId) -> U128 {
+        let reward_per_share = self.rewards.get_reward_per_share(self.lp_shares.get_total());
+        U128(self.rewards.get_unclaimed(&account_id, self.lp_shares.get(&account_id), reward_per_share))
+    }
+
+    // send the rewards of the caller, whatever cannot be delivered is deposited for them to withdraw
+    #[payable]
+    pub fn claim_rewards(&mut self) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.update_rewards(&account_id);
+
+        let mut account_rewards = self.rewards.accounts.get(&account_id).unwrap_or_default();
+        let amount = account_rewards.unclaimed;
+        ensure(amount > 0, AmmError::NoRewardsToClaim);
+        account_rewards.unclaimed = 0;
+        self.rewards.accounts.insert(&account_id, &account_rewards);
+        self.rewards.held -= amount;
+
+        let token_id = self.rewards.schedule.as_ref().or_panic(AmmError::NoRewardsToClaim).token_id.clone();
+        log!("Claiming {} of reward token {} for {}!", amount, token_id, account_id);
+        self.deliver_token_out(account_id.clone(), account_id, token_id, amount, None)
+    }
+
+    // fund a reward schedule with the tokens transferred by the owner
+    pub(crate) fn fund_rewards(
+        &mut self,
+        token: &AccountId,
+        amount: Balance,
+        start: u64,
+        end: u64,
+        rate: Balance,
+    ) -> Balance {
+        let excess = self.rewards.fund(self.lp_shares.get_total(), token, amount, start, end, rate);
+        log!("Rewards of {} token {} per second funded from {} until {}!", rate, token, start, end);
+
+        excess
+    }
+
+    pub(crate) fn update_rewards(&mut self, account_id: &AccountId) {
+        self.rewards.update(self.lp_shares.get_total());
+        self.rewards.settle(account_id, self.lp_shares.get(account_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn set_now(now: u64) {
+        testing_env!(VMContextBuilder::new().block_timestamp(now * 1_000_000_000).build());
+    }
+
+    #[test]
+    fn test_rewards_pro_rata() {
+        set_now(100);
+        let mut rewards = Rewards::new();
+        assert_eq!(rewards.fund(0, &accounts(4), 1_050, 100, 200, 10), 50);
+
+        // account 0 holds all 100 shares for the first 50 seconds
+        set_now(150);
+        rewards.update(100);
+        rewards.settle(&accounts(0), 100);
+        rewards.settle(&accounts(1), 0);
+
+        // then account 1 holds 300 of 400 shares until the end
+        set_now(300);
+        rewards.update(400);
+        assert_eq!(rewards.get_unclaimed(&accounts(0), 100, rewards.reward_per_share), 625);
+        assert_eq!(rewards.get_unclaimed(&accounts(1), 300, rewards.reward_per_share), 375);
+    }
+
+    #[test]
+    fn test_rewards_without_shares_carried_over() {
+        set_now(100);
+        let mut rewards = Rewards::new();
+        rewards.fund(0, &accounts(4), 1_000, 100, 200, 10);
+
+        // nobody holds shares for the first 30 seconds
+        set_now(130);
+        rewards.update(0);
+        rewards.settle(&accounts(0), 0);
+        set_now(200);
+        rewards.update(100);
+        assert_eq!(rewards.get_unclaimed(&accounts(0), 100, rewards.reward_per_share), 700);
+
+        // the next schedule is funded with the 300 nobody earned, so all but 200 of the 500 transferred is returned
+        assert_eq!(rewards.fund(100, &accounts(4), 500, 200, 300, 5), 300);
+        assert_eq!(rewards.held, 1_200);
+    }
+
+    #[test]
+    #[should_panic(expected = "E524: ")]
+    fn test_schedule_overlaps() {
+        set_now(100);
+        let mut rewards = Rewards::new();
+        rewards.fund(0, &accounts(4), 1_000, 100, 200, 10);
+        rewards.fund(0, &accounts(4), 1_000, 150, 250, 10);
+    }
+}
@@ -0,0 +1,168 @@
+use near_amm_math::{get_liquidity, get_proportional_shares};
+use near_amm_math::{div_round, Rounding, U256};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    json_types::U128,
+    env, log, near_bindgen, AccountId, Balance, Promise,
+};
+use std::convert::TryFrom;
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::{Contract, ContractExt, StorageKey};
+
+// shares of the liquidity held by every LP, which the swap fees and the liquidity mining rewards are shared by
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LpShares {
+    balances: LookupMap<AccountId, Balance>,
+    total: Balance,
+}
+
+impl LpShares {
+    pub fn new() -> Self {
+        Self {
+            balances: LookupMap::new(StorageKey::LpShares),
+            total: 0,
+        }
+    }
+
+    pub fn get(&self, account: &AccountId) -> Balance {
+        self.balances.get(account).unwrap_or(0)
+    }
+
+    pub fn get_total(&self) -> Balance {
+        self.total
+    }
+
+    pub fn mint(&mut self, account: &AccountId, amount: Balance) {
+        let balance = self
+            .get(account)
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        self.total = self
+            .total
+            .checked_add(amount)
+            .or_panic(AmmError::InternalOverflowError);
+        self.balances.insert(account, &balance);
+    }
+
+    pub fn burn(&mut self, account: &AccountId, amount: Balance) {
+        let balance = self.get(account);
+        ensure(amount > 0 && amount <= balance, AmmError::InsufficientShares);
+        self.total -= amount;
+        self.balances.insert(account, &(balance - amount));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the LP shares of an account
+    pub fn get_shares(&self, account_id: AccountId) -> U128 {
+        U128(self.lp_shares.get(&account_id))
+    }
+
+    pub fn get_total_shares(&self) -> U128 {
+        U128(self.lp_shares.get_total())
+    }
+
+    // add liquidity to the active pool from the caller's deposits of both tokens, in the order of get_metadata, taking
+    // them in proportion to the reserves, so adding liquidity never moves the price, and leaving the rest deposited
+    // liquidity of a single token goes through zap_in instead, which pays the swap fee on the part it swaps
+    pub fn add_liquidity(&mut self, amounts: [U128; 2], min_shares: U128) -> U128 {
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        self.assert_unlocked();
+        let account_id = env::predecessor_account_id();
+
+        let (shares, (used_a, used_b)) = get_proportional_shares(
+            self.lp_shares.get_total(),
+            (self.tokens[0].get_balance(), self.tokens[1].get_balance()),
+            (amounts[0].0, amounts[1].0),
+        )
+        .unwrap_or_panic();
+        ensure(shares > 0 && shares >= min_shares.0, AmmError::SharesBelowMinimum);
+
+        for (index, amount) in [(0, used_a), (1, used_b)] {
+            let token_id = self.tokens[index].get_address().clone();
+            self.debit_deposit(&account_id, &token_id, amount);
+            self.tokens[index].add_balance(amount);
+            self.stats.record_liquidity(&token_id, amount);
+        }
+        self.mint_shares(&account_id, shares);
+
+        log!("Liquidity of {} and {} added by {} from their deposits!", used_a, used_b, account_id);
+        U128(shares)
+    }
+
+    // burn shares for their part of both reserves, rounded down in the pool's favour, and send both tokens, each at
+    // least its minimum in the order of get_metadata, as leaving the pool is never blocked by the access list
+    // the last LP takes both reserves, so the pool awaits liquidity again
+    #[payable]
+    pub fn remove_liquidity(&mut self, shares: U128, min_amounts: [U128; 2]) -> Promise {
+        assert_one_yocto();
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        self.assert_unlocked();
+        let account_id = env::predecessor_account_id();
+
+        let total_shares = self.lp_shares.get_total();
+        let amounts = [0, 1].map(|index| {
+            let amount = div_round(
+                U256::from(self.tokens[index].get_balance()) * U256::from(shares.0),
+                U256::from(total_shares),
+                Rounding::Down,
+            );
+            Balance::try_from(amount).or_panic(AmmError::InternalOverflowError)
+        });
+        ensure(
+            amounts[0] >= min_amounts[0].0 && amounts[1] >= min_amounts[1].0,
+            AmmError::AmountOutBelowMinimum,
+        );
+        // shares worth nothing of either token are not burnt for nothing
+        ensure(amounts[0] > 0 || amounts[1] > 0, AmmError::AmountOutBelowMinimum);
+        self.burn_shares(&account_id, shares.0);
+        for (token, amount) in self.tokens.iter_mut().zip(amounts.iter()) {
+            token.set_balance(token.get_balance() - amount);
+        }
+        self.update_pool_state();
+
+        let [token_a, token_b] = [0, 1].map(|index| self.tokens[index].get_address().clone());
+        log!(
+            "Liquidity of {} shares removed by {} as {} of token {} and {} of token {}!",
+            shares.0,
+            account_id,
+            amounts[0],
+            token_a,
+            amounts[1],
+            token_b
+        );
+        let (sender_id, receiver_id) = (account_id.clone(), account_id);
+        match amounts {
+            [amount_a, 0] => self.deliver_token_out(sender_id, receiver_id, token_a, amount_a, None),
+            [0, amount_b] => self.deliver_token_out(sender_id, receiver_id, token_b, amount_b, None),
+            [amount_a, amount_b] => self
+                .deliver_token_out(sender_id.clone(), receiver_id.clone(), token_a, amount_a, None)
+                .and(self.deliver_token_out(sender_id, receiver_id, token_b, amount_b, None)),
+        }
+    }
+
+    // current liquidity of the pool, 0 until both tokens are provided
+    pub(crate) fn get_liquidity(&self) -> Balance {
+        get_liquidity(self.tokens[0].get_balance(), self.tokens[1].get_balance())
+    }
+
+    // every change of shares goes through here, so the rewards accrue on the shares held before the change
+    pub(crate) fn mint_shares(&mut self, account_id: &AccountId, amount: Balance) {
+        self.update_rewards(account_id);
+        self.lp_shares.mint(account_id, amount);
+
+        log!("{} LP shares minted for {}!", amount, account_id);
+    }
+
+    pub(crate) fn burn_shares(&mut self, account_id: &AccountId, amount: Balance) {
+        self.update_rewards(account_id);
+        self.lp_shares.burn(account_id, amount);
+
+        log!("{} LP shares burnt for {}!", amount, account_id);
+    }
+}
@@ -0,0 +1,152 @@
+use near_amm_math::U256;
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::{LookupMap, LookupSet},
+    env,
+    json_types::{U128, U64},
+    near_bindgen,
+    serde::{Deserialize, Serialize},
+    AccountId, Balance, BlockHeight, Timestamp,
+};
+
+use crate::error::*;
+use crate::util::{MAX_UNPAID_TRADERS, TRADER_RECORD_STORAGE_BYTES};
+use crate::{Contract, ContractExt, StorageKey};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenStatsMetadata {
+    token_id: AccountId,
+    // decimal strings, as the counters are 256-bit
+    pub volume: String,
+    pub fees: String,
+    pub liquidity_added: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatsMetadata {
+    pub tokens: Vec<TokenStatsMetadata>,
+    pub swap_count: U128,
+    pub unique_trader_count: U128,
+    pub last_swap_block_height: Option<U64>,
+    pub last_swap_timestamp: Option<U64>,
+}
+
+// lifetime counters of a token, 256-bit so that they never overflow
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TokenStats {
+    // swapped in or out
+    volume: U256,
+    // kept by the pool, i.e. without the share of referrers
+    fees: U256,
+    liquidity_added: U256,
+}
+
+// lifetime counters of the pool
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Stats {
+    tokens: LookupMap<AccountId, TokenStats>,
+    swap_count: u128,
+    traders: LookupSet<AccountId>,
+    unique_trader_count: u128,
+    // traders counted at the contract's expense, as they could not pay for their record
+    unpaid_trader_count: u64,
+    last_swap_block_height: Option<BlockHeight>,
+    last_swap_timestamp: Option<Timestamp>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            tokens: LookupMap::new(StorageKey::TokenStats),
+            swap_count: 0,
+            traders: LookupSet::new(StorageKey::Traders),
+            unique_trader_count: 0,
+            unpaid_trader_count: 0,
+            last_swap_block_height: None,
+            last_swap_timestamp: None,
+        }
+    }
+
+    fn update_token(&mut self, token: &AccountId, update: impl FnOnce(&mut TokenStats)) {
+        let mut token_stats = self.tokens.get(token).unwrap_or_default();
+        update(&mut token_stats);
+        self.tokens.insert(token, &token_stats);
+    }
+
+    pub fn record_swap(
+        &mut self,
+        (token_in, amount_in): (&AccountId, Balance),
+        (token_out, amount_out): (&AccountId, Balance),
+        fee: Balance,
+    ) {
+        self.update_token(token_in, |token_stats| {
+            token_stats.volume += U256::from(amount_in);
+            token_stats.fees += U256::from(fee);
+        });
+        self.update_token(token_out, |token_stats| token_stats.volume += U256::from(amount_out));
+
+        self.swap_count = self
+            .swap_count
+            .checked_add(1)
+            .or_panic(AmmError::InternalOverflowError);
+        self.last_swap_block_height = Some(env::block_height());
+        self.last_swap_timestamp = Some(env::block_timestamp());
+    }
+
+    pub fn record_liquidity(&mut self, token: &AccountId, amount: Balance) {
+        self.update_token(token, |token_stats| token_stats.liquidity_added += U256::from(amount));
+    }
+
+    pub fn is_trader(&self, account: &AccountId) -> bool {
+        self.traders.contains(account)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // count the trader of a swap the first time they trade, with the storage of their record charged to their storage
+    // balance, or at the contract's expense for at most MAX_UNPAID_TRADERS traders that cannot pay for it
+    pub(crate) fn record_trader(&mut self, trader_id: &AccountId) {
+        if self.stats.is_trader(trader_id) {
+            return;
+        }
+        if self.storage_accounts.charge(trader_id, TRADER_RECORD_STORAGE_BYTES).is_err() {
+            if self.stats.unpaid_trader_count >= MAX_UNPAID_TRADERS {
+                return;
+            }
+            self.stats.unpaid_trader_count += 1;
+        }
+
+        self.stats.traders.insert(trader_id);
+        self.stats.unique_trader_count = self
+            .stats
+            .unique_trader_count
+            .checked_add(1)
+            .or_panic(AmmError::InternalOverflowError);
+    }
+
+    // public method to get the lifetime counters of the pool
+    pub fn get_stats(&self) -> StatsMetadata {
+        StatsMetadata {
+            tokens: self
+                .tokens
+                .iter()
+                .map(|token| {
+                    let token_stats = self.stats.tokens.get(token.get_address()).unwrap_or_default();
+                    TokenStatsMetadata {
+                        token_id: token.get_address().clone(),
+                        volume: token_stats.volume.to_string(),
+                        fees: token_stats.fees.to_string(),
+                        liquidity_added: token_stats.liquidity_added.to_string(),
+                    }
+                })
+                .collect(),
+            swap_count: U128(self.stats.swap_count),
+            unique_trader_count: U128(self.stats.unique_trader_count),
+            last_swap_block_height: self.stats.last_swap_block_height.map(U64),
+            last_swap_timestamp: self.stats.last_swap_timestamp.map(U64),
+        }
+    }
+}
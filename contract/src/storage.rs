@@ -0,0 +1,205 @@
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement};
+use near_sdk::{
+    assert_one_yocto,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::U128,
+    log, near_bindgen, AccountId, Balance, Promise, StorageUsage,
+};
+
+use crate::error::*;
+use crate::util::{ACCOUNT_STORAGE_BYTES, TRADER_RECORD_STORAGE_BYTES};
+use crate::{Contract, ContractExt, StorageKey};
+
+// NEAR deposited by an account for the storage of the records it creates, e.g. its deposits, and how much of
+// the storage it pays for is taken
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountStorage {
+    deposit: Balance,
+    used_bytes: StorageUsage,
+}
+
+impl AccountStorage {
+    fn get_available(&self) -> Balance {
+        self.deposit - get_storage_cost(self.used_bytes)
+    }
+}
+
+// storage balances of the accounts registered via storage_deposit (NEP-145)
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StorageAccounts {
+    accounts: LookupMap<AccountId, AccountStorage>,
+}
+
+impl StorageAccounts {
+    pub fn new() -> Self {
+        Self {
+            accounts: LookupMap::new(StorageKey::StorageAccounts),
+        }
+    }
+
+    // take the storage of a new record from the storage balance of the account
+    pub fn charge(&mut self, account: &AccountId, bytes: StorageUsage) -> Result<(), AmmError> {
+        let mut account_storage = self.accounts.get(account).ok_or(AmmError::AccountNotRegistered)?;
+        if account_storage.get_available() < get_storage_cost(bytes) {
+            return Err(AmmError::InsufficientStorageBalance);
+        }
+        account_storage.used_bytes += bytes;
+        self.accounts.insert(account, &account_storage);
+
+        Ok(())
+    }
+
+    // give the storage of a removed record back to the storage balance of the account, saturating as records
+    // owed to an account that could not pay for them were stored at the contract's expense
+    pub fn release(&mut self, account: &AccountId, bytes: StorageUsage) {
+        if let Some(mut account_storage) = self.accounts.get(account) {
+            account_storage.used_bytes = account_storage
+                .used_bytes
+                .saturating_sub(bytes)
+                .max(ACCOUNT_STORAGE_BYTES);
+            self.accounts.insert(account, &account_storage);
+        }
+    }
+}
+
+fn get_storage_cost(bytes: StorageUsage) -> Balance {
+    Balance::from(bytes) * env::storage_byte_cost()
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    // register an account, or top up its storage balance, with the attached NEAR
+    // registration only keeps the minimum, and refunds everything for an account that is already registered
+    #[payable]
+    fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let min_balance = get_storage_cost(ACCOUNT_STORAGE_BYTES);
+
+        let refund = match self.storage_accounts.accounts.get(&account_id) {
+            Some(_) if registration_only => amount,
+            Some(mut account_storage) => {
+                account_storage.deposit += amount;
+                self.storage_accounts.accounts.insert(&account_id, &account_storage);
+                0
+            }
+            None => {
+                ensure(amount >= min_balance, AmmError::InsufficientStorageBalance);
+                let deposit = if registration_only { min_balance } else { amount };
+                log!("Registering {} with a storage balance of {}!", account_id, deposit);
+                self.storage_accounts.accounts.insert(
+                    &account_id,
+                    &AccountStorage {
+                        deposit,
+                        used_bytes: ACCOUNT_STORAGE_BYTES,
+                    },
+                );
+                amount - deposit
+            }
+        };
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+
+        self.storage_balance_of(account_id).or_panic(AmmError::AccountNotRegistered)
+    }
+
+    // send back the storage balance of the caller that no record takes, all of it when no amount is given
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account_storage = self
+            .storage_accounts
+            .accounts
+            .get(&account_id)
+            .or_panic(AmmError::AccountNotRegistered);
+        let available = account_storage.get_available();
+        let amount = amount.map_or(available, |amount| amount.0);
+        ensure(amount <= available, AmmError::InsufficientStorageBalance);
+
+        account_storage.deposit -= amount;
+        self.storage_accounts.accounts.insert(&account_id, &account_storage);
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+
+        self.storage_balance_of(account_id).or_panic(AmmError::AccountNotRegistered)
+    }
+
+    // unregister the caller and send back its storage balance, once it has no records left but the one counting it as
+    // a trader, which is kept for good along with the NEAR paying for it
+    // force is not supported, as the records hold tokens owed to the account
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        ensure(!force.unwrap_or(false), AmmError::AccountHasRecords);
+        let account_id = env::predecessor_account_id();
+        let account_storage = match self.storage_accounts.accounts.get(&account_id) {
+            Some(account_storage) => account_storage,
+            None => return false,
+        };
+        let kept_bytes = account_storage.used_bytes - ACCOUNT_STORAGE_BYTES;
+        ensure(
+            kept_bytes == 0 || (kept_bytes == TRADER_RECORD_STORAGE_BYTES && self.stats.is_trader(&account_id)),
+            AmmError::AccountHasRecords,
+        );
+
+        log!("Unregistering {}!", account_id);
+        self.storage_accounts.accounts.remove(&account_id);
+        Promise::new(account_id).transfer(account_storage.deposit - get_storage_cost(kept_bytes));
+
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(get_storage_cost(ACCOUNT_STORAGE_BYTES)),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .accounts
+            .get(&account_id)
+            .map(|account_storage| StorageBalance {
+                total: U128(account_storage.deposit),
+                available: U128(account_storage.get_available()),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_charge_and_release() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut storage_accounts = StorageAccounts::new();
+        assert_eq!(storage_accounts.charge(&accounts(0), 100), Err(AmmError::AccountNotRegistered));
+
+        storage_accounts.accounts.insert(
+            &accounts(0),
+            &AccountStorage {
+                deposit: get_storage_cost(ACCOUNT_STORAGE_BYTES + 150),
+                used_bytes: ACCOUNT_STORAGE_BYTES,
+            },
+        );
+        assert_eq!(storage_accounts.charge(&accounts(0), 100), Ok(()));
+        assert_eq!(storage_accounts.charge(&accounts(0), 100), Err(AmmError::InsufficientStorageBalance));
+
+        // the registration itself is never released
+        storage_accounts.release(&accounts(0), 200);
+        let account_storage = storage_accounts.accounts.get(&accounts(0)).unwrap();
+        assert_eq!(account_storage.used_bytes, ACCOUNT_STORAGE_BYTES);
+        assert_eq!(account_storage.get_available(), get_storage_cost(150));
+    }
+}
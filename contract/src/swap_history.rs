@@ -0,0 +1,195 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
+    json_types::{U128, U64},
+    log, near_bindgen,
+    serde::{Deserialize, Serialize},
+    store::Vector,
+    AccountId, Balance, Promise,
+};
+use std::cmp::Ordering;
+
+use crate::error::*;
+use crate::util::{MAX_SWAP_HISTORY_SIZE, SWAP_RECORD_STORAGE_BYTES};
+use crate::{Contract, ContractExt, StorageKey};
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapRecord {
+    pub sender_id: AccountId,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    pub amount_in: U128,
+    pub amount_out: U128,
+    // whole fee charged on the input, including the share of the referrer
+    pub fee: U128,
+    pub timestamp: U64,
+}
+
+// ring buffer of the last swaps, which overwrites the oldest swap once it holds as many swaps as its size
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SwapHistory {
+    swaps: Vector<SwapRecord>,
+    size: u32,
+    // where the oldest swap is, once the buffer is full
+    next_index: u32,
+}
+
+impl SwapHistory {
+    // empty until the owner pays for its storage
+    pub fn new() -> Self {
+        Self {
+            swaps: Vector::new(StorageKey::SwapHistory),
+            size: 0,
+            next_index: 0,
+        }
+    }
+
+    pub fn record(&mut self, swap: SwapRecord) {
+        if self.swaps.len() < self.size {
+            self.swaps.push(swap);
+        } else if self.size > 0 {
+            self.swaps.set(self.next_index, swap);
+            self.next_index = (self.next_index + 1) % self.size;
+        }
+    }
+
+    // swaps from the newest to the oldest
+    pub fn iter(&self) -> impl Iterator<Item = &SwapRecord> {
+        let len = self.swaps.len();
+        (0..len).map(move |index| {
+            let index = (u64::from(self.next_index) + u64::from(len) - 1 - u64::from(index)) % u64::from(len);
+            self.swaps.get(index as u32).or_panic(AmmError::InternalIndexError)
+        })
+    }
+
+    // keep the newest swaps that fit into the new size, laid out from the oldest so the buffer starts over
+    pub fn resize(&mut self, size: u32) {
+        let mut swaps: Vec<SwapRecord> = self.iter().take(size as usize).cloned().collect();
+        swaps.reverse();
+
+        self.swaps.clear();
+        self.swaps.extend(swaps);
+        self.size = size;
+        self.next_index = 0;
+    }
+}
+
+// storage reserved for the given number of swaps
+fn get_storage_cost(size: u32) -> Balance {
+    Balance::from(size) * Balance::from(SWAP_RECORD_STORAGE_BYTES) * env::storage_byte_cost()
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the number of last swaps kept
+    pub fn get_swap_history_size(&self) -> u32 {
+        self.swap_history.size
+    }
+
+    // public method to get the last swaps from the newest, where index 0 is the newest swap
+    pub fn get_swaps(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<SwapRecord> {
+        self.swap_history
+            .iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.map_or(usize::MAX, |limit| limit.0 as usize))
+            .cloned()
+            .collect()
+    }
+
+    // public method to get the last swaps of an account from the newest
+    pub fn get_swaps_by_account(&self, account_id: AccountId, limit: Option<U64>) -> Vec<SwapRecord> {
+        self.swap_history
+            .iter()
+            .filter(|swap| swap.sender_id == account_id)
+            .take(limit.map_or(usize::MAX, |limit| limit.0 as usize))
+            .cloned()
+            .collect()
+    }
+
+    // set how many of the last swaps are kept, only contract's owner can set it
+    // the owner attaches the storage cost of every swap added to the size, and gets back the excess
+    // as well as the storage cost of every swap removed from it
+    #[payable]
+    pub fn set_swap_history_size(&mut self, size: u32) {
+        self.assert_owner();
+        ensure(size <= MAX_SWAP_HISTORY_SIZE, AmmError::InvalidSwapHistorySize);
+
+        let attached_deposit = env::attached_deposit();
+        let refund = match size.cmp(&self.swap_history.size) {
+            Ordering::Greater => {
+                let storage_cost = get_storage_cost(size - self.swap_history.size);
+                ensure(attached_deposit >= storage_cost, AmmError::InsufficientStorageDeposit);
+                attached_deposit - storage_cost
+            }
+            _ => attached_deposit + get_storage_cost(self.swap_history.size - size),
+        };
+
+        log!("Swap history keeps the last {} swaps instead of {}!", size, self.swap_history.size);
+        self.swap_history.resize(size);
+
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn swap(sender_id: AccountId, amount_in: Balance) -> SwapRecord {
+        SwapRecord {
+            sender_id,
+            token_in: accounts(2),
+            token_out: accounts(3),
+            amount_in: U128(amount_in),
+            amount_out: U128(amount_in / 2),
+            fee: U128(0),
+            timestamp: U64(0),
+        }
+    }
+
+    fn amounts_in(swap_history: &SwapHistory) -> Vec<Balance> {
+        swap_history.iter().map(|swap| swap.amount_in.0).collect()
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut swap_history = SwapHistory::new();
+        swap_history.record(swap(accounts(0), 1));
+        assert!(amounts_in(&swap_history).is_empty());
+
+        swap_history.resize(3);
+        (1..=5).for_each(|amount_in| swap_history.record(swap(accounts(0), amount_in)));
+        assert_eq!(amounts_in(&swap_history), vec![5, 4, 3]);
+
+        // shrinking keeps the newest swaps
+        swap_history.resize(2);
+        assert_eq!(amounts_in(&swap_history), vec![5, 4]);
+        swap_history.record(swap(accounts(0), 6));
+        assert_eq!(amounts_in(&swap_history), vec![6, 5]);
+
+        swap_history.resize(4);
+        (7..=9).for_each(|amount_in| swap_history.record(swap(accounts(0), amount_in)));
+        assert_eq!(amounts_in(&swap_history), vec![9, 8, 7, 6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E523: ")]
+    fn test_storage_not_paid() {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(1), accounts(2), accounts(3));
+
+        testing_env!(context.attached_deposit(get_storage_cost(10) - 1).build());
+        contract.set_swap_history_size(10);
+    }
+}
@@ -1,26 +1,53 @@
 use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
+    json_types::{U128, U64},
     log,
     serde::{Deserialize, Serialize},
     AccountId,
     Balance,
+    BlockHeight,
 };
 
 use crate::error::*;
-use crate::util::*;
+use crate::upgrade::TokenV1;
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct TokenMetadata {
-    address: AccountId,
-    name: String,
-    ticker: String,
-    decimal: u8,
+    pub address: AccountId,
+    pub name: String,
+    pub ticker: String,
+    pub decimal: u8,
     // ratio as compared to the first token in the token vector, so first token's ratio is always 1
     pub ratio: f64,
 }
 
+// recorded balance of a token against its balance as last reported by the token contract
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BalanceDifference {
+    address: AccountId,
+    balance: U128,
+    // held by the contract for open limit orders, deposits of accounts, rewards and transfers still moving, which is
+    // not part of the liquidity
+    escrowed: U128,
+    // none until the balance has been reported at least once
+    observed_balance: Option<U128>,
+    pub observed_block_height: Option<U64>,
+    // reported balance above the recorded balance, which can be skimmed
+    pub surplus: U128,
+    // reported balance below the recorded balance, which sync writes off
+    pub deficit: U128,
+}
+
+// balance of the contract as reported by the token contract itself, which can differ from the recorded balance
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct ObservedBalance {
+    pub amount: Balance,
+    pub block_height: BlockHeight,
+}
+
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Token {
     address: AccountId,
@@ -28,6 +55,7 @@ pub struct Token {
     ticker: Option<String>,
     decimal: Option<u8>,
     balance: Balance,
+    observed_balance: Option<ObservedBalance>,
 }
 
 impl Token {
@@ -38,15 +66,11 @@ impl Token {
             ticker: None,
             decimal: None,
             balance: 0,
+            observed_balance: None,
         }
     }
 
     pub fn set_metadata(&mut self, metadata: FungibleTokenMetadata) {
-        assert!(
-            metadata.decimals >= MIN_DECIMAL && metadata.decimals <= MAX_DECIMAL,
-            "{}",
-            INVALID_TOKEN_DECIMAL
-        );
         log!(
             "Set token {} name: {}, ticker: {}, decimal: {}",
             self.address,
@@ -66,18 +90,22 @@ impl Token {
             name: self
                 .name
                 .as_ref()
-                .expect(TOKEN_METADATA_NOT_INITIALISED)
+                .or_panic(AmmError::TokenMetadataNotInitialised)
                 .clone(),
             ticker: self
                 .ticker
                 .as_ref()
-                .expect(TOKEN_METADATA_NOT_INITIALISED)
+                .or_panic(AmmError::TokenMetadataNotInitialised)
                 .clone(),
-            decimal: self.decimal.expect(TOKEN_METADATA_NOT_INITIALISED),
+            decimal: self.decimal.or_panic(AmmError::TokenMetadataNotInitialised),
             ratio: 1f64,
         }
     }
 
+    pub fn has_metadata(&self) -> bool {
+        self.decimal.is_some()
+    }
+
     pub fn get_address(&self) -> &AccountId {
         &self.address
     }
@@ -87,7 +115,7 @@ impl Token {
     }
 
     pub fn get_decimal(&self) -> u8 {
-        self.decimal.expect(TOKEN_METADATA_NOT_INITIALISED)
+        self.decimal.or_panic(AmmError::TokenMetadataNotInitialised)
     }
 
     pub fn get_balance(&self) -> Balance {
@@ -98,22 +126,67 @@ impl Token {
         self.balance = self
             .balance
             .checked_add(amount)
-            .expect(INTERNAL_OVERFLOW_ERROR);
+            .or_panic(AmmError::InternalOverflowError);
+    }
+
+    // overwrite the recorded balance, e.g. with the balance reported by the token contract
+    pub fn set_balance(&mut self, amount: Balance) {
+        self.balance = amount;
+    }
+
+    pub fn get_observed_balance(&self) -> Option<ObservedBalance> {
+        self.observed_balance
+    }
+
+    pub fn get_balance_difference(&self, escrowed: Balance) -> BalanceDifference {
+        let held = self.balance.saturating_add(escrowed);
+        let observed_amount = self.observed_balance.map_or(held, |observed| observed.amount);
+        BalanceDifference {
+            address: self.address.clone(),
+            balance: U128(self.balance),
+            escrowed: U128(escrowed),
+            observed_balance: self.observed_balance.map(|observed| U128(observed.amount)),
+            observed_block_height: self.observed_balance.map(|observed| U64(observed.block_height)),
+            surplus: U128(observed_amount.saturating_sub(held)),
+            deficit: U128(held.saturating_sub(observed_amount)),
+        }
+    }
+
+    pub fn set_observed_balance(&mut self, observed_balance: ObservedBalance) {
+        self.observed_balance = Some(observed_balance);
     }
 
     pub fn subtract_balance(&mut self, amount: Balance) {
         self.balance = self
             .balance
             .checked_sub(amount)
-            .expect(INTERNAL_OVERFLOW_ERROR);
-        assert!(self.balance > 0, "{}", INVALID_TOKEN_BALANCE);
+            .or_panic(AmmError::InternalOverflowError);
+        ensure(self.balance > 0, AmmError::InvalidTokenBalance);
+    }
+
+    // whole tokens of this token the pool holds per whole base token, computed from the raw balances as scaling to
+    // a canonical decimal can round a balance with many decimals down to zero, and finite for any non-zero balances
+    // as a ratio of u128 balances times 10 ^ 255 stays below f64::MAX
+    pub fn get_ratio_to(&self, base: &Token) -> f64 {
+        if self.balance == 0 || base.balance == 0 {
+            return 0f64;
+        }
+        let decimal_difference = i32::from(base.get_decimal()) - i32::from(self.get_decimal());
+
+        self.balance as f64 / base.balance as f64 * 10f64.powi(decimal_difference)
     }
+}
 
-    pub fn get_canonical_balance(&self) -> Balance {
-        amount_to_canonical_amount(
-            self.balance,
-            self.decimal.expect(TOKEN_METADATA_NOT_INITIALISED)
-        )
+impl From<TokenV1> for Token {
+    fn from(token: TokenV1) -> Self {
+        Self {
+            address: token.address,
+            name: token.name,
+            ticker: token.ticker,
+            decimal: token.decimal,
+            balance: token.balance,
+            observed_balance: None,
+        }
     }
 }
 
@@ -125,6 +198,7 @@ mod tests {
     fn test_get_set_methods() {
         let address = AccountId::new_unchecked(String::from("test.near"));
         let mut token = Token::new(address.clone());
+        assert!(!token.has_metadata());
         let spec = "test-spec".to_string();
         let name = "test-name".to_string();
         let symbol = "TST".to_string();
@@ -138,6 +212,7 @@ mod tests {
             reference_hash: None,
             decimals,
         });
+        assert!(token.has_metadata());
         assert_eq!(token.get_metadata().address.as_str(), address.as_str());
         assert_eq!(token.get_metadata().name, name);
         assert_eq!(token.get_metadata().ticker, symbol);
@@ -163,35 +238,21 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn test_illegal_set_metadata_min() {
+    fn test_set_metadata_any_decimal() {
         let address = AccountId::new_unchecked(String::from("test.near"));
         let mut token = Token::new(address.clone());
-        token.set_metadata(FungibleTokenMetadata {
-            spec: "test-spec".to_string(),
-            name: "test-name".to_string(),
-            symbol: "test-sym".to_string(),
-            icon: None,
-            reference: None,
-            reference_hash: None,
-            decimals: 0,
-        });
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_illegal_set_metadata_max() {
-        let address = AccountId::new_unchecked(String::from("test.near"));
-        let mut token = Token::new(address.clone());
-        token.set_metadata(FungibleTokenMetadata {
-            spec: "test-spec".to_string(),
-            name: "test-name".to_string(),
-            symbol: "test-sym".to_string(),
-            icon: None,
-            reference: None,
-            reference_hash: None,
-            decimals: 30,
-        });
+        for decimals in [0u8, 30, u8::MAX] {
+            token.set_metadata(FungibleTokenMetadata {
+                spec: "test-spec".to_string(),
+                name: "test-name".to_string(),
+                symbol: "test-sym".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals,
+            });
+            assert_eq!(token.get_decimal(), decimals);
+        }
     }
 
     #[test]
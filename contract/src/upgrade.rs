@@ -0,0 +1,189 @@
+use near_amm_math::get_liquidity;
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env,
+    store::Vector,
+    AccountId, Balance,
+};
+
+use crate::access_list::AccessList;
+use crate::error::*;
+use crate::in_flight::TransfersInFlight;
+use crate::lifecycle::PoolState;
+use crate::deposit::Deposits;
+use crate::limit_order::OrderBook;
+use crate::referral::Referrals;
+use crate::rewards::Rewards;
+use crate::shares::LpShares;
+use crate::stats::Stats;
+use crate::storage::StorageAccounts;
+use crate::swap_history::SwapHistory;
+use crate::token::Token;
+use crate::util::{DEFAULT_FLASH_LOAN_FEE_BPS, DEFAULT_SWAP_FEE_BPS};
+use crate::{Contract, StorageKey};
+
+// storage key holding the layout version of the contract state, kept outside of the state itself
+// because the layout deployed before versioning was introduced has no room for it
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+// layout version of the current `Contract` struct, bump it whenever a field is added, removed or changed
+// once a layout is deployed, and keep that layout below as a legacy struct converted in `From<VersionedContract>`
+pub const CURRENT_STATE_VERSION: u8 = 2;
+
+// token layout of the baseline, before the token balances reported by the token contracts were recorded
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokenV1 {
+    pub(crate) address: AccountId,
+    pub(crate) name: Option<String>,
+    pub(crate) ticker: Option<String>,
+    pub(crate) decimal: Option<u8>,
+    pub(crate) balance: Balance,
+}
+
+// layout deployed before versioning was introduced
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV1 {
+    owner_address: AccountId,
+    tokens: Vector<TokenV1>,
+    functional: bool,
+}
+
+impl From<ContractV1> for Contract {
+    fn from(mut contract: ContractV1) -> Self {
+        // a pool that was functional had both metadata and liquidity, otherwise the tokens tell how far it got
+        let pool_state = if contract.functional {
+            PoolState::Active
+        } else if !contract.tokens.iter().all(|token| token.decimal.is_some()) {
+            PoolState::AwaitingMetadata
+        } else {
+            PoolState::AwaitingLiquidity
+        };
+
+        // tokens are rewritten with the current layout under the same storage prefix, so the old entries go first
+        let legacy_tokens: Vec<TokenV1> = contract.tokens.drain(..).collect();
+        contract.tokens.flush();
+        let mut tokens: Vector<Token> = Vector::new(StorageKey::Token);
+        legacy_tokens
+            .into_iter()
+            .for_each(|token| tokens.push(token.into()));
+
+        // the owner was the only LP, so they hold every share of the liquidity provided so far
+        let mut lp_shares = LpShares::new();
+        let liquidity = get_liquidity(tokens[0].get_balance(), tokens[1].get_balance());
+        if liquidity > 0 {
+            lp_shares.mint(&contract.owner_address, liquidity);
+        }
+
+        Self {
+            owner_address: contract.owner_address,
+            pending_owner_address: None,
+            tokens,
+            // empty until the owner pays for its storage
+            swap_history: SwapHistory::new(),
+            pool_state,
+            flash_loan: None,
+            flash_loan_fee_bps: DEFAULT_FLASH_LOAN_FEE_BPS,
+            order_book: OrderBook::new(),
+            deposits: Deposits::new(),
+            swap_fee_bps: DEFAULT_SWAP_FEE_BPS,
+            referrals: Referrals::new(),
+            dynamic_fee: None,
+            swap_limits: None,
+            access_list: AccessList::new(),
+            wrap_near_id: None,
+            // counting starts with the upgrade, as earlier swaps were not recorded
+            stats: Stats::new(),
+            lp_shares,
+            rewards: Rewards::new(),
+            transfers_in_flight: TransfersInFlight::new(),
+            reconciling: false,
+            storage_accounts: StorageAccounts::new(),
+        }
+    }
+}
+
+// every state layout that has ever been deployed, so that any of them can be migrated to the current one
+pub enum VersionedContract {
+    V1(ContractV1),
+    // boxed, as the current layout is much bigger than the legacy ones
+    V2(Box<Contract>),
+}
+
+impl VersionedContract {
+    // read the stored state using the layout it was written with
+    pub fn read() -> Self {
+        // state written before versioning was introduced has no version stored
+        let version = env::storage_read(STATE_VERSION_KEY)
+            .map(|value| *value.first().or_panic(AmmError::InvalidStateVersion))
+            .unwrap_or(1);
+
+        match version {
+            1 => Self::V1(env::state_read().or_panic(AmmError::StateNotInitialised)),
+            2 => Self::V2(Box::new(env::state_read().or_panic(AmmError::StateNotInitialised))),
+            _ => panic_with(AmmError::InvalidStateVersion),
+        }
+    }
+}
+
+impl From<VersionedContract> for Contract {
+    fn from(versioned: VersionedContract) -> Self {
+        match versioned {
+            VersionedContract::V1(contract) => contract.into(),
+            VersionedContract::V2(contract) => *contract,
+        }
+    }
+}
+
+// record that the state is now stored using the current layout
+pub fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[CURRENT_STATE_VERSION]);
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let contract = accounts(0);
+        let owner = accounts(1);
+
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(contract.clone())
+            .signer_account_id(owner.clone())
+            .predecessor_account_id(owner.clone());
+        testing_env!(context.build());
+
+        let mut tokens = Vector::new(StorageKey::Token);
+        for (address, balance) in [(accounts(2), 100), (accounts(3), 10)] {
+            tokens.push(TokenV1 {
+                address,
+                name: Some("test-name".to_string()),
+                ticker: Some("TST".to_string()),
+                decimal: Some(8),
+                balance,
+            });
+        }
+        tokens.flush();
+        env::state_write(&ContractV1 {
+            owner_address: owner.clone(),
+            tokens,
+            functional: true,
+        });
+
+        let contract: Contract = VersionedContract::read().into();
+        assert_eq!(contract.owner_address, owner);
+        assert_eq!(contract.pending_owner_address, None);
+        assert_eq!(contract.tokens.len(), 2);
+        assert_eq!(contract.tokens[1].get_address().as_str(), accounts(3).as_str());
+        assert_eq!(contract.tokens[1].get_balance(), 10);
+        assert_eq!(contract.tokens[1].get_decimal(), 8);
+        assert!(contract.tokens[1].get_observed_balance().is_none());
+        assert_eq!(contract.pool_state, PoolState::Active);
+        assert_eq!(contract.lp_shares.get(&owner), 31);
+    }
+}
@@ -1,55 +1,34 @@
-use near_sdk::{Balance};
-use uint::construct_uint;
+use near_sdk::Gas;
 
-use crate::error::*;
-
-// only allow decimal precision of range below
-pub const MIN_DECIMAL: u8 = 1;
-pub const MAX_DECIMAL: u8 = 24;
 pub const FT_TRANSFER_DEPOSIT_YOCTO_NEAR: u128 = 1;
+pub const GAS_FOR_MIGRATE: Gas = Gas(50_000_000_000_000);
+pub const DEFAULT_FLASH_LOAN_FEE_BPS: u16 = 9;
+// swaps are free until the owner sets a fee, so the pools deployed before fees keep their pricing
+pub const DEFAULT_SWAP_FEE_BPS: u16 = 0;
+pub const MAX_SWAP_FEE_BPS: u16 = 1_000;
+// price movement that adds one basis point to the dynamic swap fee, e.g. a move of 1% adds 10 basis points
+pub const PRICE_MOVE_BPS_PER_FEE_BPS: u64 = 10;
+// bounds the gas of filling limit orders, so a swap only checks this many of the cheapest orders
+pub const MAX_ORDERS_CHECKED_PER_SWAP: usize = 5;
+pub const MAX_OPEN_ORDERS_PER_ACCOUNT: usize = 50;
+pub const MAX_SWAP_HISTORY_SIZE: u32 = 1_000;
+// upper bound of the storage of a swap in the history: three account ids of at most 64 bytes, three amounts,
+// a timestamp, the key and the 40 bytes charged for every storage record
+pub const SWAP_RECORD_STORAGE_BYTES: u64 = 320;
+// upper bound of the storage of a registration: an account id of at most 64 bytes, its storage balance, the key and
+// the 40 bytes charged for every storage record
+pub const ACCOUNT_STORAGE_BYTES: u64 = 140;
+// upper bound of the storage of a deposit: two account ids of at most 64 bytes, an amount, the key and the 40 bytes
+// charged for every storage record
+pub const DEPOSIT_RECORD_STORAGE_BYTES: u64 = 200;
+// upper bound of the storage of a trader counted by the statistics: an account id of at most 64 bytes, the key and
+// the 40 bytes charged for every storage record
+pub const TRADER_RECORD_STORAGE_BYTES: u64 = 120;
+// upper bound of the storage of a limit order: the order with two account ids of at most 64 bytes and its entries in
+// the indexes by price and by owner, each a tree node and a map entry, with the 40 bytes charged for every record
+pub const ORDER_RECORD_STORAGE_BYTES: u64 = 800;
+// traders that cannot pay for their record are counted at the contract's expense, but only this many of them
+pub const MAX_UNPAID_TRADERS: u64 = 10_000;
+// precision of the reward per share, so that rewards of pools with many shares are not rounded away
+pub const REWARD_PER_SHARE_DECIMAL: usize = 24;
 
-construct_uint! {
-    // 256-bit unsigned integer to prevent constant product calculation from overflow
-    pub struct U256(4);
-}
-
-// convert amount to a canonical form so that amount with different decimals can be compared and calculated
-pub fn amount_to_canonical_amount(amount: Balance, decimal: u8) -> Balance {
-    let factor = 10u128
-        .checked_pow((MAX_DECIMAL - decimal) as u32)
-        .expect(INTERNAL_OVERFLOW_ERROR);
-
-    amount
-        .checked_mul(factor)
-        .expect(INTERNAL_OVERFLOW_ERROR)
-}
-
-// convert canonical amount back to amount to be stored in contract state
-pub fn canonical_amount_to_amount(amount: Balance, decimal: u8) -> Balance {
-    let factor = 10u128
-        .checked_pow((MAX_DECIMAL - decimal) as u32)
-        .expect(INTERNAL_OVERFLOW_ERROR);
-
-    amount
-        .checked_div(factor)
-        .expect(INTERNAL_OVERFLOW_ERROR)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_amount_to_canonical_amount() {
-        let expected_balance = 100u128;
-        let balance = amount_to_canonical_amount(1, 22);
-        assert_eq!(expected_balance, balance);
-    }
-
-    #[test]
-    fn test_canonical_amount_to_amount() {
-        let expected_balance = 1u128;
-        let balance = canonical_amount_to_amount(100u128, 22);
-        assert_eq!(expected_balance, balance);
-    }
-}
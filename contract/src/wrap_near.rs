@@ -0,0 +1,126 @@
+use near_sdk::{
+    env, ext_contract,
+    json_types::U128,
+    log, near_bindgen, AccountId, Balance, Promise, PromiseResult,
+};
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::util::FT_TRANSFER_DEPOSIT_YOCTO_NEAR;
+use crate::{Contract, ContractExt};
+
+// interface of the wNEAR contract, which mints wNEAR for the NEAR attached and burns it for NEAR sent back
+#[allow(dead_code)]
+#[ext_contract(ext_wrap_near)]
+trait WrapNear {
+    fn near_deposit(&mut self);
+
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+#[near_bindgen]
+impl Contract {
+    // public method to get the pool token that is wNEAR, none when native NEAR cannot be swapped
+    pub fn get_wrap_near(&self) -> Option<AccountId> {
+        self.wrap_near_id.clone()
+    }
+
+    // set which pool token is wNEAR so that native NEAR can be swapped, only contract's owner can set it
+    pub fn set_wrap_near(&mut self, wrap_near_id: AccountId) {
+        self.assert_owner();
+        ensure(
+            self.tokens.iter().any(|token| token.check_address(&wrap_near_id)),
+            AmmError::WrapNearNotSupported,
+        );
+
+        log!("Native NEAR is swapped through {}!", wrap_near_id);
+        self.wrap_near_id = Some(wrap_near_id);
+    }
+
+    // swap the attached NEAR for the other token of the pool, wrapping it into wNEAR first
+    #[payable]
+    pub fn swap_near(&mut self) -> Promise {
+        let wrap_near_id = self.wrap_near_id.clone().or_panic(AmmError::WrapNearNotSupported);
+        let amount = env::attached_deposit();
+        ensure(amount > 0, AmmError::InvalidAmountTransferred);
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        self.assert_unlocked();
+        let sender_id = env::predecessor_account_id();
+        ensure(self.access_list.is_allowed(&sender_id), AmmError::SwapperNotAllowed);
+
+        log!("Wrapping {} yoctoNEAR of {} to swap!", amount, sender_id);
+        // the wNEAR is credited to the contract before the callback swaps it
+        self.transfers_in_flight.add(&wrap_near_id, amount);
+        ext_wrap_near::ext(wrap_near_id)
+            .with_attached_deposit(amount)
+            .near_deposit()
+            .then(Self::ext(env::current_account_id()).post_wrap_near(sender_id, U128(amount)))
+    }
+
+    // callback for wrapping the NEAR of swap_near, the NEAR is sent back if it could not be wrapped,
+    // and the wNEAR is deposited for the sender if it could not be swapped
+    #[private]
+    pub fn post_wrap_near(&mut self, sender_id: AccountId, amount: U128) {
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
+        let wrap_near_id = self.wrap_near_id.clone().or_panic(AmmError::WrapNearNotSupported);
+
+        self.transfers_in_flight.settle(&wrap_near_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                // the pool may have changed since swap_near was called
+                let swapped = if self.pool_state != PoolState::Active || self.is_locked() {
+                    Err(AmmError::AmmNotFunctionalYet)
+                } else {
+                    self.swap(&sender_id, &wrap_near_id, amount.0, None)
+                };
+
+                match swapped {
+                    Ok((token_out, amount_out)) => {
+                        self.deliver_token_out(sender_id.clone(), sender_id, token_out.clone(), amount_out, None);
+                        self.fill_crossed_orders(&token_out);
+                    }
+                    Err(error) => {
+                        log!("Swap of wrapped NEAR failed, depositing it for {}: {}", sender_id, error);
+                        self.credit_owed_deposit(&sender_id, &wrap_near_id, amount.0);
+                    }
+                }
+            }
+            PromiseResult::Failed => {
+                log!("Wrapping NEAR failed, refunding {} yoctoNEAR to {}!", amount.0, sender_id);
+                Promise::new(sender_id).transfer(amount.0);
+            }
+        }
+    }
+
+    // unwrap wNEAR swapped out of the pool and send it to the sender as native NEAR
+    pub(crate) fn send_near_out(&mut self, sender_id: AccountId, amount: Balance) -> Promise {
+        let wrap_near_id = self.wrap_near_id.clone().or_panic(AmmError::WrapNearNotSupported);
+        self.transfers_in_flight.add(&wrap_near_id, amount);
+
+        ext_wrap_near::ext(wrap_near_id)
+            .with_attached_deposit(FT_TRANSFER_DEPOSIT_YOCTO_NEAR)
+            .near_withdraw(U128(amount))
+            .then(Self::ext(env::current_account_id()).post_unwrap_near(sender_id, U128(amount)))
+    }
+
+    // callback for unwrapping in send_near_out, the wNEAR is deposited for the sender if it could not be unwrapped
+    #[private]
+    pub fn post_unwrap_near(&mut self, sender_id: AccountId, amount: U128) {
+        ensure(env::promise_results_count() == 1, AmmError::PromiseTooManyResults);
+        let wrap_near_id = self.wrap_near_id.clone().or_panic(AmmError::WrapNearNotSupported);
+
+        self.transfers_in_flight.settle(&wrap_near_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                log!("Sending {} yoctoNEAR to {}!", amount.0, sender_id);
+                Promise::new(sender_id).transfer(amount.0);
+            }
+            PromiseResult::Failed => {
+                log!("Unwrapping NEAR failed, depositing {} of wNEAR for {}!", amount.0, sender_id);
+                self.credit_owed_deposit(&sender_id, &wrap_near_id, amount.0);
+            }
+        }
+    }
+}
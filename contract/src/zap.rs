@@ -0,0 +1,106 @@
+use near_amm_math::{div_round, get_shares_minted, get_zap_in_swap_amount, Rounding, U256};
+use near_sdk::{assert_one_yocto, env, json_types::U128, log, near_bindgen, AccountId, Balance, Promise};
+use std::convert::TryFrom;
+
+use crate::error::*;
+use crate::lifecycle::PoolState;
+use crate::{Contract, ContractExt};
+
+#[near_bindgen]
+impl Contract {
+    // provide liquidity from a single token, swapping the part that makes the rest match the reserves after the swap
+    // a swap that would break the swap limits leaves the pool untouched and returns the limit it would break,
+    // while minting less than min_shares fails so the whole amount is refunded
+    pub(crate) fn zap_in(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount: Balance,
+        min_shares: Balance,
+    ) -> Result<Balance, AmmError> {
+        let balance_in = self.get_token_pair(token_in).0.get_balance();
+        let amount_swapped = get_zap_in_swap_amount(amount, balance_in, self.get_swap_fee()).unwrap_or_panic();
+        let (token_out, amount_out) = self.swap(sender_id, token_in, amount_swapped, None)?;
+
+        let liquidity_before = self.get_liquidity();
+        for (token_address, amount) in [(token_in, amount - amount_swapped), (&token_out, amount_out)] {
+            self.tokens
+                .iter_mut()
+                .find(|token| token.check_address(token_address))
+                .or_panic(AmmError::InvalidTokenTransferred)
+                .add_balance(amount);
+            self.stats.record_liquidity(token_address, amount);
+        }
+
+        let shares = get_shares_minted(self.lp_shares.get_total(), liquidity_before, self.get_liquidity())
+            .unwrap_or_panic();
+        ensure(shares > 0 && shares >= min_shares, AmmError::SharesBelowMinimum);
+        self.mint_shares(sender_id, shares);
+
+        log!(
+            "Liquidity {} of token {} added by {}, of which {} was swapped for {} of token {}!",
+            amount,
+            token_in,
+            sender_id,
+            amount_swapped,
+            amount_out,
+            token_out
+        );
+        Ok(shares)
+    }
+
+    // burn shares for their part of both reserves, swap the part in the other token into token_out against the
+    // remaining reserves, and send the total with one ft_transfer, as leaving the pool is never blocked by the access list
+    // the last LP would leave nothing to swap against, so they leave via remove_liquidity instead
+    #[payable]
+    pub fn remove_liquidity_one_token(&mut self, shares: U128, token_out: AccountId, min_amount_out: U128) -> Promise {
+        assert_one_yocto();
+        ensure(self.pool_state == PoolState::Active, AmmError::AmmNotFunctionalYet);
+        self.assert_unlocked();
+        let account_id = env::predecessor_account_id();
+        let (token_out, token_in) = self.get_token_pair(&token_out);
+        let (token_in, token_out) = (token_in.get_address().clone(), token_out.get_address().clone());
+
+        let total_shares = self.lp_shares.get_total();
+        ensure(shares.0 != total_shares, AmmError::LastLpRemovalInOneToken);
+
+        // rounded down in the pool's favour
+        let [amount_in, amount_out] = [&token_in, &token_out].map(|token_address| {
+            let balance = self.get_token_pair(token_address).0.get_balance();
+            let amount = div_round(
+                U256::from(balance) * U256::from(shares.0),
+                U256::from(total_shares),
+                Rounding::Down,
+            );
+            Balance::try_from(amount).or_panic(AmmError::InternalOverflowError)
+        });
+        self.burn_shares(&account_id, shares.0);
+        self.tokens.iter_mut().for_each(|token| {
+            if token.check_address(&token_in) {
+                token.subtract_balance(amount_in);
+            } else {
+                token.subtract_balance(amount_out);
+            }
+        });
+
+        let amount_swapped = if amount_in > 0 {
+            self.swap(&account_id, &token_in, amount_in, None)
+                .unwrap_or_else(|error| panic_with(error))
+                .1
+        } else {
+            0
+        };
+        let amount_out = amount_out + amount_swapped;
+        ensure(amount_out >= min_amount_out.0, AmmError::AmountOutBelowMinimum);
+        self.fill_crossed_orders(&token_out);
+
+        log!(
+            "Liquidity of {} shares removed by {} as {} of token {}!",
+            shares.0,
+            account_id,
+            amount_out,
+            token_out
+        );
+        self.deliver_token_out(account_id.clone(), account_id, token_out, amount_out, None)
+    }
+}
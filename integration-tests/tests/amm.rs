@@ -0,0 +1,298 @@
+// integration tests of the AMM against a local sandbox node, with two deployments of the fungible token contract
+// the sandbox is downloaded on the first run unless NEAR_SANDBOX_BIN_PATH points to one, and the AMM is the wasm
+// built by script/build.sh, so run script/build.sh before `cargo test -p integration-tests -- --ignored`
+use near_workspaces::network::Sandbox;
+use near_workspaces::result::ExecutionFinalResult;
+use near_workspaces::types::NearToken;
+use near_workspaces::{Account, AccountId, Contract, Worker};
+use serde_json::{json, Value};
+
+const FUNGIBLE_TOKEN_WASM: &str = "../res/fungible_token.wasm";
+const AMM_WASM: &str = "../res/near_automated_market_maker.wasm";
+const TOTAL_SUPPLY: u128 = 1_000_000_000_000_000;
+// the same as script/var.conf
+const LP_INIT_ETH: u128 = 1_000_000_000_000;
+const LP_INIT_SOL: u128 = 10_000_000_000;
+const USER_INIT_ETH: u128 = 10_000_000_000;
+const USER_INIT_SOL: u128 = 100_000_000;
+
+struct Pool {
+    worker: Worker<Sandbox>,
+    eth: Contract,
+    sol: Contract,
+    amm: Contract,
+    // owner of the AMM, the only one who can lp_deposit until the pool is active
+    lp: Account,
+    user: Account,
+}
+
+impl Pool {
+    async fn create_account(&self, name: &str) -> anyhow::Result<Account> {
+        create_account(&self.worker, name).await
+    }
+
+    async fn ft_balance_of(&self, token: &Contract, account_id: &AccountId) -> anyhow::Result<u128> {
+        let balance: String = token
+            .view("ft_balance_of")
+            .args_json(json!({ "account_id": account_id }))
+            .await?
+            .json()?;
+
+        Ok(balance.parse()?)
+    }
+
+    async fn ft_transfer_call(
+        &self,
+        sender: &Account,
+        token: &Contract,
+        amount: u128,
+        msg: &str,
+    ) -> anyhow::Result<ExecutionFinalResult> {
+        let result = sender
+            .call(token.id(), "ft_transfer_call")
+            .args_json(json!({ "receiver_id": self.amm.id(), "amount": amount.to_string(), "msg": msg }))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn view_amm(&self, method_name: &str, args: Value) -> anyhow::Result<Value> {
+        Ok(self.amm.view(method_name).args_json(args).await?.json()?)
+    }
+
+    async fn get_amount_out(&self, token_in: &Contract, amount_in: u128) -> anyhow::Result<u128> {
+        let amount_out = self
+            .view_amm("get_amount_out", json!({ "token_in": token_in.id(), "amount_in": amount_in.to_string() }))
+            .await?;
+
+        Ok(amount_out.as_str().unwrap_or_default().parse()?)
+    }
+
+    // liquidity of the profile in script/var.conf, which activates the pool
+    async fn provide_liquidity(&self) -> anyhow::Result<()> {
+        for (token, amount) in [(&self.eth, LP_INIT_ETH / 100), (&self.sol, LP_INIT_SOL / 100)] {
+            self.ft_transfer_call(&self.lp, token, amount, "lp_deposit")
+                .await?
+                .into_result()?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn create_account(worker: &Worker<Sandbox>, name: &str) -> anyhow::Result<Account> {
+    let account = worker
+        .root_account()?
+        .create_subaccount(name)
+        .initial_balance(NearToken::from_near(50))
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(account)
+}
+
+// token contract whose owner holds the total supply
+async fn deploy_token(worker: &Worker<Sandbox>, name: &str, symbol: &str, decimals: u8) -> anyhow::Result<Contract> {
+    let account = create_account(worker, &symbol.to_lowercase()).await?;
+    let token = account
+        .deploy(&std::fs::read(FUNGIBLE_TOKEN_WASM)?)
+        .await?
+        .into_result()?;
+    token
+        .call("new")
+        .args_json(json!({
+            "owner_id": token.id(),
+            "total_supply": TOTAL_SUPPLY.to_string(),
+            "metadata": { "spec": "ft-1.0.0", "name": name, "symbol": symbol, "decimals": decimals },
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(token)
+}
+
+async fn register(token: &Contract, account_id: &AccountId) -> anyhow::Result<()> {
+    token
+        .call("storage_deposit")
+        .args_json(json!({ "account_id": account_id }))
+        .deposit(NearToken::from_yoctonear(1_250_000_000_000_000_000_000))
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(())
+}
+
+// register an account with a token and send it some of the supply
+async fn fund(token: &Contract, account_id: &AccountId, amount: u128) -> anyhow::Result<()> {
+    register(token, account_id).await?;
+    token
+        .call("ft_transfer")
+        .args_json(json!({ "receiver_id": account_id, "amount": amount.to_string() }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(())
+}
+
+// the accounts and tokens of script/setup.sh, with the AMM initialised by the lp but without liquidity yet
+async fn setup() -> anyhow::Result<Pool> {
+    let worker = near_workspaces::sandbox().await?;
+    let eth = deploy_token(&worker, "Ethereum", "ETH", 8).await?;
+    let sol = deploy_token(&worker, "Solana", "SOL", 6).await?;
+    let lp = create_account(&worker, "lp").await?;
+    let user = create_account(&worker, "user").await?;
+    let amm = create_account(&worker, "amm")
+        .await?
+        .deploy(&std::fs::read(AMM_WASM)?)
+        .await?
+        .into_result()?;
+
+    fund(&eth, lp.id(), LP_INIT_ETH).await?;
+    fund(&sol, lp.id(), LP_INIT_SOL).await?;
+    fund(&eth, user.id(), USER_INIT_ETH).await?;
+    fund(&sol, user.id(), USER_INIT_SOL).await?;
+    register(&eth, amm.id()).await?;
+    register(&sol, amm.id()).await?;
+
+    // new fires ft_metadata at both tokens, whose callbacks run within the same transaction
+    lp.call(amm.id(), "new")
+        .args_json(json!({ "owner_id": lp.id(), "token_a_id": eth.id(), "token_b_id": sol.id() }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(Pool { worker, eth, sol, amm, lp, user })
+}
+
+fn failure_messages(result: &ExecutionFinalResult) -> String {
+    format!("{:?}", result.receipt_failures())
+}
+
+#[tokio::test]
+#[ignore = "needs a NEAR sandbox"]
+async fn test_init_loads_metadata() -> anyhow::Result<()> {
+    let pool = setup().await?;
+    assert_eq!(pool.view_amm("get_pool_state", json!({})).await?, json!("AwaitingLiquidity"));
+
+    pool.provide_liquidity().await?;
+    let metadata = pool.view_amm("get_metadata", json!({})).await?;
+    assert_eq!(metadata["owner"], json!(pool.lp.id()));
+    assert_eq!(metadata["tokens"][0]["ticker"], json!("ETH"));
+    assert_eq!(metadata["tokens"][0]["decimal"], json!(8));
+    assert_eq!(metadata["tokens"][1]["name"], json!("Solana"));
+    assert_eq!(metadata["tokens"][1]["decimal"], json!(6));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs a NEAR sandbox"]
+async fn test_lp_deposits() -> anyhow::Result<()> {
+    let pool = setup().await?;
+    pool.ft_transfer_call(&pool.lp, &pool.eth, LP_INIT_ETH / 100, "lp_deposit")
+        .await?
+        .into_result()?;
+    assert_eq!(pool.view_amm("get_pool_state", json!({})).await?, json!("AwaitingLiquidity"));
+
+    pool.ft_transfer_call(&pool.lp, &pool.sol, LP_INIT_SOL / 100, "lp_deposit")
+        .await?
+        .into_result()?;
+    assert_eq!(pool.view_amm("get_pool_state", json!({})).await?, json!("Active"));
+    assert_eq!(pool.ft_balance_of(&pool.eth, pool.amm.id()).await?, LP_INIT_ETH / 100);
+    assert_eq!(pool.ft_balance_of(&pool.sol, pool.amm.id()).await?, LP_INIT_SOL / 100);
+    // shares are the geometric mean of the reserves, i.e. sqrt(10 ^ 10 * 10 ^ 8)
+    let shares = pool.view_amm("get_shares", json!({ "account_id": pool.lp.id() })).await?;
+    assert_eq!(shares, json!("1000000000"));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs a NEAR sandbox"]
+async fn test_swaps_in_both_directions() -> anyhow::Result<()> {
+    let pool = setup().await?;
+    pool.provide_liquidity().await?;
+
+    for (token_in, token_out, amount_in) in [(&pool.eth, &pool.sol, 100_000_000), (&pool.sol, &pool.eth, 1_000_000)] {
+        let amount_out = pool.get_amount_out(token_in, amount_in).await?;
+        assert!(amount_out > 0);
+        let balance_in = pool.ft_balance_of(token_in, pool.user.id()).await?;
+        let balance_out = pool.ft_balance_of(token_out, pool.user.id()).await?;
+
+        pool.ft_transfer_call(&pool.user, token_in, amount_in, "swap")
+            .await?
+            .into_result()?;
+        assert_eq!(pool.ft_balance_of(token_in, pool.user.id()).await?, balance_in - amount_in);
+        assert_eq!(pool.ft_balance_of(token_out, pool.user.id()).await?, balance_out + amount_out);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs a NEAR sandbox"]
+async fn test_payout_to_unregistered_user_is_deposited() -> anyhow::Result<()> {
+    let pool = setup().await?;
+    pool.provide_liquidity().await?;
+    // registered with ETH only, so the SOL bought cannot be transferred to it
+    let trader = pool.create_account("trader").await?;
+    fund(&pool.eth, trader.id(), USER_INIT_ETH).await?;
+
+    let amount_out = pool.get_amount_out(&pool.eth, 100_000_000).await?;
+    let sol_held = pool.ft_balance_of(&pool.sol, pool.amm.id()).await?;
+    let result = pool.ft_transfer_call(&trader, &pool.eth, 100_000_000, "swap").await?;
+    assert!(result.logs().iter().any(|log| log.contains("was not delivered")));
+    // the swap stands, and the AMM keeps the SOL for the trader
+    assert_eq!(pool.ft_balance_of(&pool.eth, trader.id()).await?, USER_INIT_ETH - 100_000_000);
+    assert_eq!(pool.ft_balance_of(&pool.sol, pool.amm.id()).await?, sol_held);
+    let deposits = pool.view_amm("get_deposits", json!({ "account_id": trader.id() })).await?;
+    assert_eq!(deposits[1]["amount"], json!(amount_out.to_string()));
+
+    // withdrawable once registered
+    register(&pool.sol, trader.id()).await?;
+    trader
+        .call(pool.amm.id(), "withdraw")
+        .args_json(json!({ "token_id": pool.sol.id() }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    assert_eq!(pool.ft_balance_of(&pool.sol, trader.id()).await?, amount_out);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs a NEAR sandbox"]
+async fn test_rejected_messages_are_refunded() -> anyhow::Result<()> {
+    let pool = setup().await?;
+
+    // swaps wait for liquidity, which only the owner provides until the pool is active
+    for (msg, code) in [("swap", "E500"), ("lp_deposit", "E601")] {
+        let result = pool.ft_transfer_call(&pool.user, &pool.eth, 1_000, msg).await?;
+        assert!(failure_messages(&result).contains(code), "{}", msg);
+    }
+
+    pool.provide_liquidity().await?;
+    let eth_held = pool.ft_balance_of(&pool.eth, pool.amm.id()).await?;
+    for (msg, code) in [("buy low", "E600"), (r#"{"limit_order": {}}"#, "E600")] {
+        let result = pool.ft_transfer_call(&pool.user, &pool.eth, 1_000, msg).await?;
+        assert!(failure_messages(&result).contains(code), "{}", msg);
+    }
+
+    // the token contract refunded every rejected transfer
+    assert_eq!(pool.ft_balance_of(&pool.eth, pool.user.id()).await?, USER_INIT_ETH);
+    assert_eq!(pool.ft_balance_of(&pool.eth, pool.amm.id()).await?, eth_held);
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use crate::{div_round, Balance, Rounding, U256};
+
+// decimal precision that off-chain callers can scale amounts of every token to, so that they can be compared
+// the contract prices on raw amounts and never scales them
+pub const CANONICAL_DECIMAL: u8 = 24;
+
+// 10 ^ exponent, or None when it does not fit into U256, i.e. when exponent is above 77
+fn power_of_ten(exponent: u8) -> Option<U256> {
+    U256::from(10u8).checked_pow(U256::from(exponent))
+}
+
+// convert amount to a canonical form so that amount with different decimals can be compared, e.g. for ratios
+// scaling up to the canonical decimal is exact and cannot overflow as u128 * 10 ^ 24 fits into U256,
+// whereas scaling down from a bigger decimal drops digits, which are rounded towards the given direction
+pub fn amount_to_canonical_amount(amount: Balance, decimal: u8, rounding: Rounding) -> U256 {
+    if decimal <= CANONICAL_DECIMAL {
+        U256::from(amount) * U256::exp10(usize::from(CANONICAL_DECIMAL - decimal))
+    } else {
+        match power_of_ten(decimal - CANONICAL_DECIMAL) {
+            Some(factor) => div_round(U256::from(amount), factor, rounding),
+            // the factor is bigger than any u128 amount, so only the rounding decides the result
+            None if rounding == Rounding::Up && amount > 0 => U256::one(),
+            None => U256::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_to_canonical_amount() {
+        let expected_balance = U256::from(100u128);
+        let balance = amount_to_canonical_amount(1, 22, Rounding::Down);
+        assert_eq!(expected_balance, balance);
+    }
+
+    #[test]
+    fn test_zero_decimal_does_not_overflow() {
+        let balance = amount_to_canonical_amount(Balance::MAX, 0, Rounding::Down);
+        assert_eq!(balance, U256::from(Balance::MAX) * power_of_ten(CANONICAL_DECIMAL).unwrap());
+    }
+
+    #[test]
+    fn test_big_decimal_rounding() {
+        // 30 decimals means 6 digits are dropped when scaling down to the canonical decimal
+        assert_eq!(amount_to_canonical_amount(1_999_999, 30, Rounding::Down), U256::from(1u8));
+        assert_eq!(amount_to_canonical_amount(1_999_999, 30, Rounding::Up), U256::from(2u8));
+        assert_eq!(amount_to_canonical_amount(2_000_000, 30, Rounding::Up), U256::from(2u8));
+    }
+
+    #[test]
+    fn test_max_decimal_rounding() {
+        assert_eq!(amount_to_canonical_amount(Balance::MAX, u8::MAX, Rounding::Down), U256::zero());
+        assert_eq!(amount_to_canonical_amount(Balance::MAX, u8::MAX, Rounding::Up), U256::one());
+        assert_eq!(amount_to_canonical_amount(0, u8::MAX, Rounding::Up), U256::zero());
+    }
+}
@@ -0,0 +1,86 @@
+// pricing core of the AMM, shared by the contract and off-chain code such as bots so both get exactly the same
+// results, which is why it depends on neither near-sdk nor std
+#![no_std]
+
+#[cfg(any(test, feature = "borsh"))]
+extern crate std;
+
+mod canonical;
+mod liquidity;
+mod swap;
+mod u256;
+
+use core::convert::TryFrom;
+use core::fmt;
+
+pub use canonical::{amount_to_canonical_amount, CANONICAL_DECIMAL};
+pub use liquidity::{get_liquidity, get_proportional_shares, get_shares_minted, get_zap_in_swap_amount};
+pub use swap::{
+    get_amount_after_fee, get_amount_before_fee, get_amount_in, get_amount_out, get_fee, get_fee_share,
+    get_price_move_bps, quote_amount_in, quote_amount_out,
+};
+pub use u256::{div_round, Rounding, U256};
+
+// amount of a token in its smallest unit, the same as near_sdk::Balance
+pub type Balance = u128;
+
+// fees are expressed in basis points, i.e. 1/10000
+pub const BASIS_POINTS_DENOMINATOR: u16 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MathError {
+    // result does not fit into a Balance
+    Overflow,
+    // the pool does not hold enough of the token out
+    InsufficientLiquidity,
+    // fee or fee share above 10000 basis points, or of 10000 where the maths divides by what is left after it
+    InvalidFee,
+}
+
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MathError::Overflow => write!(f, "result overflows a balance"),
+            MathError::InsufficientLiquidity => write!(f, "insufficient liquidity"),
+            MathError::InvalidFee => write!(f, "invalid fee basis points"),
+        }
+    }
+}
+
+fn to_balance(value: U256) -> Result<Balance, MathError> {
+    Balance::try_from(value).map_err(|_| MathError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    // every case of golden.txt must be reproduced exactly, as the contract does for its quotes
+    #[test]
+    fn test_golden() {
+        for line in include_str!("../golden.txt").lines().filter(|line| !line.starts_with('#')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let amount = |index: usize| fields[index].parse::<Balance>().unwrap();
+            let fee_bps = |index: usize| fields[index].parse::<u16>().unwrap();
+            let expected = *fields.last().unwrap();
+
+            let result = match fields[0] {
+                "canonical" => {
+                    let rounding = if fields[3] == "up" { Rounding::Up } else { Rounding::Down };
+                    let canonical_amount = amount_to_canonical_amount(amount(1), fields[2].parse().unwrap(), rounding);
+                    assert_eq!(canonical_amount, U256::from_dec_str(expected).unwrap(), "{}", line);
+                    continue;
+                }
+                "amount_out" => quote_amount_out(amount(1), amount(2), amount(3), fee_bps(4)),
+                "amount_in" => quote_amount_in(amount(1), amount(2), amount(3), fee_bps(4)),
+                "liquidity" => Ok(get_liquidity(amount(1), amount(2))),
+                "shares_minted" => get_shares_minted(amount(1), amount(2), amount(3)),
+                "zap_in" => get_zap_in_swap_amount(amount(1), amount(2), fee_bps(3)),
+                kind => panic!("unknown golden case {}", kind),
+            };
+            assert_eq!(result, Ok(expected.parse().unwrap()), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{div_round, to_balance, Balance, MathError, Rounding, BASIS_POINTS_DENOMINATOR, U256};
+
+// liquidity of the pool as the geometric mean of its reserves, which LP shares are minted against
+pub fn get_liquidity(balance_a: Balance, balance_b: Balance) -> Balance {
+    // never more than the bigger balance, so it always fits
+    (U256::from(balance_a) * U256::from(balance_b)).integer_sqrt().low_u128()
+}
+
+// shares minted for growing the liquidity, pro rata to the shares already issued and rounded down in the pool's favour
+// the first shares are the liquidity itself
+pub fn get_shares_minted(
+    total_shares: Balance,
+    liquidity_before: Balance,
+    liquidity_after: Balance,
+) -> Result<Balance, MathError> {
+    if total_shares == 0 || liquidity_before == 0 {
+        return Ok(liquidity_after);
+    }
+    let shares = div_round(
+        U256::from(total_shares) * U256::from(liquidity_after.saturating_sub(liquidity_before)),
+        U256::from(liquidity_before),
+        Rounding::Down,
+    );
+
+    to_balance(shares)
+}
+
+// shares minted for adding both tokens at once, pro rata to the smaller of the two ratios to the reserves and rounded
+// down, along with the amounts of both tokens those shares take, rounded up, so the rest stays with the provider
+pub fn get_proportional_shares(
+    total_shares: Balance,
+    (balance_a, balance_b): (Balance, Balance),
+    (amount_a, amount_b): (Balance, Balance),
+) -> Result<(Balance, (Balance, Balance)), MathError> {
+    if total_shares == 0 || balance_a == 0 || balance_b == 0 {
+        return Err(MathError::InsufficientLiquidity);
+    }
+    let total_shares = U256::from(total_shares);
+    let shares = [(amount_a, balance_a), (amount_b, balance_b)]
+        .iter()
+        .map(|&(amount, balance)| div_round(U256::from(amount) * total_shares, U256::from(balance), Rounding::Down))
+        .min()
+        .unwrap_or_default();
+    // never more than the amounts themselves, so they always fit
+    let [used_a, used_b] = [balance_a, balance_b]
+        .map(|balance| div_round(shares * U256::from(balance), total_shares, Rounding::Up).low_u128());
+
+    Ok((to_balance(shares)?, (used_a, used_b)))
+}
+
+// part of a single token amount to swap so that the rest and the output match the reserves after the swap,
+// i.e. the positive root of (1 - f) * s ^ 2 + (2 - f) * r * s - a * r = 0 for fee f, reserve in r and amount a,
+// scaled by 10000 so the fee stays in basis points
+pub fn get_zap_in_swap_amount(amount: Balance, balance_in: Balance, fee_bps: u16) -> Result<Balance, MathError> {
+    if fee_bps >= BASIS_POINTS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+    let denominator = U256::from(BASIS_POINTS_DENOMINATOR);
+    let after_fee = U256::from(BASIS_POINTS_DENOMINATOR - fee_bps);
+    let linear = (denominator + after_fee) * U256::from(balance_in);
+    let discriminant = linear
+        .checked_mul(linear)
+        .and_then(|square| {
+            (after_fee * denominator * U256::from(4u8))
+                .checked_mul(U256::from(amount) * U256::from(balance_in))
+                .and_then(|product| square.checked_add(product))
+        })
+        .ok_or(MathError::Overflow)?;
+    let amount_swapped = div_round(discriminant.integer_sqrt() - linear, after_fee * U256::from(2u8), Rounding::Down);
+
+    to_balance(amount_swapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_shares_minted() {
+        assert_eq!(get_liquidity(100, 10), 31);
+        assert_eq!(get_liquidity(Balance::MAX, Balance::MAX), Balance::MAX);
+        assert_eq!(get_shares_minted(0, 0, 31), Ok(31));
+        // doubling the liquidity doubles the shares
+        assert_eq!(get_shares_minted(31, 31, 62), Ok(31));
+        assert_eq!(get_shares_minted(31, 31, 32), Ok(1));
+        assert_eq!(get_shares_minted(Balance::MAX, 1, 3), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn test_get_proportional_shares() {
+        // the token a amount only covers half of the token b amount
+        assert_eq!(get_proportional_shares(100, (1_000, 2_000), (100, 400)), Ok((10, (100, 200))));
+        // rounding leaves the pool a bit more of both tokens than the shares are worth
+        assert_eq!(get_proportional_shares(100, (1_000, 999), (15, 15)), Ok((1, (10, 10))));
+        assert_eq!(get_proportional_shares(100, (1_000, 1_000), (0, 100)), Ok((0, (0, 0))));
+        assert_eq!(get_proportional_shares(0, (1_000, 1_000), (100, 100)), Err(MathError::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_get_zap_in_swap_amount() {
+        assert_eq!(get_zap_in_swap_amount(1_000, 1_000, 0), Ok(414));
+        // the fee leaves less output, so a bit more gets swapped
+        assert_eq!(get_zap_in_swap_amount(1_000, 1_000, 1_000), Ok(436));
+        assert_eq!(get_zap_in_swap_amount(1, 1_000_000, 0), Ok(0));
+        assert_eq!(get_zap_in_swap_amount(1, 1_000_000, 10_000), Err(MathError::InvalidFee));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{div_round, to_balance, Balance, MathError, Rounding, BASIS_POINTS_DENOMINATOR, U256};
+
+// x * y = k constant product market making formula, solved for the amount that leaves the pool
+// calculated on the raw amounts, as decimals do not change the product and scaling reserves would lose dust
+// the output is rounded down so that k never decreases
+pub fn get_amount_out(amount_in: Balance, balance_in: Balance, balance_out: Balance) -> Balance {
+    let amount_in = U256::from(amount_in);
+    let amount_out = div_round(
+        amount_in * U256::from(balance_out),
+        U256::from(balance_in) + amount_in,
+        Rounding::Down,
+    );
+
+    // never more than balance_out, so it always fits
+    amount_out.low_u128()
+}
+
+// x * y = k constant product market making formula, solved for the amount that needs to enter the pool
+// the required input is rounded up so that k never decreases
+pub fn get_amount_in(amount_out: Balance, balance_in: Balance, balance_out: Balance) -> Result<Balance, MathError> {
+    if amount_out >= balance_out {
+        return Err(MathError::InsufficientLiquidity);
+    }
+    let amount_in = div_round(
+        U256::from(balance_in) * U256::from(amount_out),
+        U256::from(balance_out - amount_out),
+        Rounding::Up,
+    );
+
+    to_balance(amount_in)
+}
+
+// fee charged on an amount, rounded up in the pool's favour
+pub fn get_fee(amount: Balance, fee_bps: u16) -> Result<Balance, MathError> {
+    if fee_bps > BASIS_POINTS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+    let fee = div_round(
+        U256::from(amount) * U256::from(fee_bps),
+        U256::from(BASIS_POINTS_DENOMINATOR),
+        Rounding::Up,
+    );
+
+    to_balance(fee)
+}
+
+// what is left of an amount once the fee is charged on it, which is the part that gets priced by the swap
+pub fn get_amount_after_fee(amount: Balance, fee_bps: u16) -> Result<Balance, MathError> {
+    Ok(amount - get_fee(amount, fee_bps)?)
+}
+
+// smallest amount that is left with at least the given amount once the fee is charged on it
+pub fn get_amount_before_fee(amount: Balance, fee_bps: u16) -> Result<Balance, MathError> {
+    if fee_bps >= BASIS_POINTS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+    let amount_before_fee = div_round(
+        U256::from(amount) * U256::from(BASIS_POINTS_DENOMINATOR),
+        U256::from(BASIS_POINTS_DENOMINATOR - fee_bps),
+        Rounding::Up,
+    );
+    let mut amount_before_fee = to_balance(amount_before_fee)?;
+    // the fee is rounded up, which can take one more unit than the exact inverse accounts for
+    while get_amount_after_fee(amount_before_fee, fee_bps)? < amount {
+        amount_before_fee = amount_before_fee.checked_add(1).ok_or(MathError::Overflow)?;
+    }
+
+    Ok(amount_before_fee)
+}
+
+// how much of the other token a swap of amount_in gives after the fee, rounded down
+pub fn quote_amount_out(
+    amount_in: Balance,
+    balance_in: Balance,
+    balance_out: Balance,
+    fee_bps: u16,
+) -> Result<Balance, MathError> {
+    Ok(get_amount_out(get_amount_after_fee(amount_in, fee_bps)?, balance_in, balance_out))
+}
+
+// how much of the other token needs to be swapped to get amount_out including the fee, rounded up
+pub fn quote_amount_in(
+    amount_out: Balance,
+    balance_in: Balance,
+    balance_out: Balance,
+    fee_bps: u16,
+) -> Result<Balance, MathError> {
+    get_amount_before_fee(get_amount_in(amount_out, balance_in, balance_out)?, fee_bps)
+}
+
+// relative change of the price of token in, i.e. balance out / balance in, in basis points of the price before
+// saturating for moves too large to measure
+pub fn get_price_move_bps(balances_before: (Balance, Balance), balances_after: (Balance, Balance)) -> u64 {
+    let (balance_in_before, balance_out_before) = balances_before;
+    let (balance_in_after, balance_out_after) = balances_after;
+    let before = U256::from(balance_out_before) * U256::from(balance_in_after);
+    let after = U256::from(balance_out_after) * U256::from(balance_in_before);
+    let change = if before > after { before - after } else { after - before };
+
+    change
+        .checked_mul(U256::from(BASIS_POINTS_DENOMINATOR))
+        .and_then(|change| change.checked_div(before))
+        .filter(|price_move| *price_move <= U256::from(u64::MAX))
+        .map_or(u64::MAX, |price_move| price_move.as_u64())
+}
+
+// part of a fee that is shared, rounded down in the pool's favour
+pub fn get_fee_share(fee: Balance, share_bps: u16) -> Result<Balance, MathError> {
+    if share_bps > BASIS_POINTS_DENOMINATOR {
+        return Err(MathError::InvalidFee);
+    }
+    let share = div_round(
+        U256::from(fee) * U256::from(share_bps),
+        U256::from(BASIS_POINTS_DENOMINATOR),
+        Rounding::Down,
+    );
+
+    to_balance(share)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // reserve of a token with the given decimals, so that every decimals combination gets covered
+    fn scaled(amount: u64, decimal: u32) -> Balance {
+        Balance::from(amount).saturating_mul(10u128.pow(decimal))
+    }
+
+    #[test]
+    fn test_get_amount_out() {
+        assert_eq!(get_amount_out(100, 1000, 1000), 90);
+        assert_eq!(get_amount_out(1, 1000, 1000), 0);
+    }
+
+    #[test]
+    fn test_get_amount_in() {
+        assert_eq!(get_amount_in(90, 1000, 1000), Ok(99));
+        assert_eq!(get_amount_in(1, 1000, 1000), Ok(2));
+        assert_eq!(get_amount_in(1000, 1000, 1000), Err(MathError::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_get_fee() {
+        assert_eq!(get_fee(10_000, 9), Ok(9));
+        assert_eq!(get_fee(10_001, 9), Ok(10));
+        assert_eq!(get_fee(0, 9), Ok(0));
+        assert_eq!(get_fee(100, 10_001), Err(MathError::InvalidFee));
+    }
+
+    #[test]
+    fn test_get_amount_before_fee() {
+        assert_eq!(get_amount_after_fee(10_000, 30), Ok(9_970));
+        assert_eq!(get_amount_before_fee(9_970, 30), Ok(10_000));
+        assert_eq!(get_amount_before_fee(100, 0), Ok(100));
+        assert_eq!(get_amount_before_fee(100, 10_000), Err(MathError::InvalidFee));
+        assert_eq!(get_amount_before_fee(Balance::MAX, 30), Err(MathError::Overflow));
+        assert_eq!(get_fee_share(10, 2_500), Ok(2));
+    }
+
+    #[test]
+    fn test_quotes() {
+        // 1_000 pays a fee of 3, so the swap prices 997
+        assert_eq!(quote_amount_out(1_000, 10_000, 10_000, 30), Ok(906));
+        assert_eq!(quote_amount_in(906, 10_000, 10_000, 30), Ok(1_000));
+    }
+
+    #[test]
+    fn test_get_price_move_bps() {
+        // price of token in falls from 1 to 0.81
+        assert_eq!(get_price_move_bps((1_000, 1_000), (1_100, 910)), 1_727);
+        assert_eq!(get_price_move_bps((1_000, 1_000), (1_000, 1_000)), 0);
+        assert_eq!(get_price_move_bps((1_000, 0), (1_000, 1_000)), u64::MAX);
+    }
+
+    proptest! {
+        #[test]
+        fn test_swap_never_decreases_k(
+            balance_in in 1u64..,
+            balance_out in 1u64..,
+            amount_in in 1u64..,
+            decimal_in in 0u32..=18,
+            decimal_out in 0u32..=18,
+        ) {
+            let balance_in = scaled(balance_in, decimal_in);
+            let balance_out = scaled(balance_out, decimal_out);
+            let amount_in = scaled(amount_in, decimal_in) / 3;
+
+            let amount_out = get_amount_out(amount_in, balance_in, balance_out);
+            prop_assert!(amount_out < balance_out);
+
+            let k_before = U256::from(balance_in) * U256::from(balance_out);
+            let k_after = (U256::from(balance_in) + U256::from(amount_in)) * U256::from(balance_out - amount_out);
+            prop_assert!(k_after >= k_before);
+        }
+
+        #[test]
+        fn test_required_input_never_decreases_k(
+            balance_in in 1u64..,
+            balance_out in 2u64..,
+            amount_out in 1u64..,
+            decimal_in in 0u32..=18,
+            decimal_out in 0u32..=18,
+        ) {
+            let balance_in = scaled(balance_in, decimal_in);
+            let balance_out = scaled(balance_out, decimal_out);
+            let amount_out = Balance::from(amount_out) % balance_out;
+            prop_assume!(amount_out > 0);
+
+            // taking almost the whole reserve can require more than a u128 in
+            let amount_in = get_amount_in(amount_out, balance_in, balance_out);
+            prop_assume!(amount_in != Err(MathError::Overflow));
+            let amount_in = amount_in.unwrap();
+            prop_assert!(get_amount_out(amount_in, balance_in, balance_out) >= amount_out);
+
+            let k_before = U256::from(balance_in) * U256::from(balance_out);
+            let k_after = (U256::from(balance_in) + U256::from(amount_in)) * U256::from(balance_out - amount_out);
+            prop_assert!(k_after >= k_before);
+        }
+
+        #[test]
+        fn test_amount_before_fee_is_smallest(amount in 0u64.., fee_bps in 0u16..BASIS_POINTS_DENOMINATOR) {
+            let amount = Balance::from(amount);
+            let amount_before_fee = get_amount_before_fee(amount, fee_bps).unwrap();
+            prop_assert!(get_amount_after_fee(amount_before_fee, fee_bps).unwrap() >= amount);
+            prop_assert!(amount_before_fee == 0 || get_amount_after_fee(amount_before_fee - 1, fee_bps).unwrap() < amount);
+        }
+    }
+}
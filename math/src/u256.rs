@@ -0,0 +1,68 @@
+// lint attributes do not reach into the expansion of construct_uint, so the type gets a module of its own and
+// only the generated code is exempt from the lints newer clippy versions trip on
+mod generated {
+    #![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+    use uint::construct_uint;
+
+    construct_uint! {
+        // 256-bit unsigned integer to prevent constant product calculation from overflow
+        pub struct U256(4);
+    }
+}
+
+pub use self::generated::U256;
+
+// stored as its little-endian words, so counters that must never overflow can be kept in state
+#[cfg(feature = "borsh")]
+impl borsh::BorshSerialize for U256 {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.0.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshDeserialize for U256 {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(Self(<[u64; 4]>::deserialize(buf)?))
+    }
+}
+
+// direction to round to when a conversion cannot be exact, so the caller always decides who gets the dust
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+// division that rounds towards the given direction instead of always truncating
+pub fn div_round(numerator: U256, denominator: U256, rounding: Rounding) -> U256 {
+    let quotient = numerator / denominator;
+    if rounding == Rounding::Up && quotient * denominator != numerator {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_round() {
+        assert_eq!(div_round(U256::from(7u8), U256::from(2u8), Rounding::Down), U256::from(3u8));
+        assert_eq!(div_round(U256::from(7u8), U256::from(2u8), Rounding::Up), U256::from(4u8));
+        assert_eq!(div_round(U256::from(8u8), U256::from(2u8), Rounding::Up), U256::from(4u8));
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_borsh() {
+        use borsh::{BorshDeserialize, BorshSerialize};
+
+        let value = U256::from(u128::MAX) * U256::from(3u8);
+        let bytes = value.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(U256::try_from_slice(&bytes).unwrap(), value);
+    }
+}
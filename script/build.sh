@@ -1,7 +1,6 @@
 #!/bin/bash
 set -e
 
-cd "`dirname $0`"/../contract
-cargo build --all --target wasm32-unknown-unknown --release
-cd ..
-cp contract/target/wasm32-unknown-unknown/release/*.wasm ./res/
+cd "`dirname $0`"/..
+cargo build -p near-automated-market-maker --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/*.wasm ./res/